@@ -34,6 +34,7 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use std::fs;
 use std::time::Duration;
+use glaze::ast::FileId;
 use glaze::parser::parse;
 
 fn criterion_benchmark(c: &mut Criterion) {
@@ -44,7 +45,7 @@ fn criterion_benchmark(c: &mut Criterion) {
 
     c.bench_function(
         "parse",
-        |b| b.iter(|| parse(black_box(&input))),
+        |b| b.iter(|| parse(black_box(&input), FileId::default())),
     );
 
     c.finish();
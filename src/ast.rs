@@ -32,10 +32,46 @@
  */
 
 use fnv::FnvHashMap;
+use std::fmt;
+
+/// Identifies the source file a span points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(pub u32);
+
+/// A range of bytes within a source file.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Span {
+	pub file: FileId,
+	pub start: usize,
+	pub end: usize,
+}
+
+impl Span {
+	pub fn new(file: FileId, start: usize, end: usize) -> Self {
+		Self { file, start, end }
+	}
+
+	/// Returns the smallest span covering both `self` and `other`.
+	pub fn to(self, other: Span) -> Self {
+		Self::new(self.file, self.start.min(other.start), self.end.max(other.end))
+	}
+}
+
+impl fmt::Debug for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}..{}", self.file.0, self.start, self.end)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Type {
+	pub kind: TypeKind,
+	pub span: Span,
+}
 
 #[allow(dead_code)] // Clear up a few warnings
-#[derive(Debug)]
-pub enum Type {
+#[derive(Debug, Clone)]
+pub enum TypeKind {
 	Number,
 	String,
 	Hex,
@@ -45,14 +81,30 @@ pub enum Type {
 	List(Box<Type>),
 	Record(FnvHashMap<String, Type>),
 	Function(Vec<Type>),
+	Enum(String),
 	Alias(String),
 }
 
+/// A piece of an interpolated string.
+#[derive(Debug, Clone)]
+pub enum StringPart {
+	Text(String),
+	Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+	pub kind: ExprKind,
+	pub span: Span,
+}
+
 #[allow(dead_code)] // Clear up a few warnings
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub enum ExprKind {
 	Number(f32),
 	String(String),
+	/// A string with values spliced into it, which is still a String.
+	Interpolated(Vec<StringPart>),
 	Symbol(String),
 	Hex(String),
 	Dimension(f32, String),
@@ -66,21 +118,58 @@ pub enum Expr {
 	Call(String, Vec<Expr>),
 	Index(Box<Expr>, Box<Expr>),
 	If(Box<Expr>, Vec<Expr>, Vec<Expr>),
+	Lambda(Vec<String>, Box<Expr>),
+	/// The element being styled, `&`, or its parent, `~/`.
+	Element(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+	Left,
+	Right,
+	None,
+}
+
+/// An infix operator. Built in operators have no backing function.
+#[derive(Debug, Clone)]
+pub struct Operator {
+	pub precedence: u8,
+	pub associativity: Associativity,
+	pub function: Option<String>,
+}
+
+/// A `// line` or `/* block */` comment, without its delimiters.
+#[derive(Debug, Clone)]
+pub struct Comment {
+	pub text: String,
+	pub block: bool,
+	pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Node {
+	pub kind: NodeKind,
+	pub span: Span,
+	/// Comments on the lines just before the node, or after it on its first
+	/// line.
+	pub comments: Vec<Comment>,
 }
 
 #[allow(dead_code)] // Clear up a few warnings
 #[derive(Debug)]
-pub enum Node {
+pub enum NodeKind {
 	Selector(Vec<String>, Vec<Node>),
 	Function(String, Vec<String>, Vec<Type>, Vec<Node>),
 	Property(String, Vec<Expr>),
 	Definition(String, Expr),
 	Enum(String, Vec<String>),
 	TypeAlias(String, Type),
+	Operator(String, Operator),
 	AtCSS(Expr),
 	AtData(FnvHashMap<String, Expr>),
-	AtEvent(Vec<Node>),
+	AtEvent(String, Vec<Node>),
 	Where(Vec<(String, Expr)>),
+	If(Expr, Vec<Node>, Vec<Node>),
 	Return(Expr),
 	Expr(Expr),
 }
@@ -0,0 +1,69 @@
+/*
+ *  Copyright (c) 2020, GiraffeKey
+ *
+ *  All rights reserved.
+ *
+ *  Redistribution and use in source and binary forms, with or without
+ *  modification, are permitted provided that the following conditions are met:
+ *
+ *  Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ *
+ *  Redistributions in binary form must reproduce the above
+ *  copyright notice, this list of conditions and the following
+ *  disclaimer in the documentation and/or other materials provided
+ *  with the distribution.
+ *
+ *  Neither the name of GiraffeKey nor the names of other
+ *  contributors may be used to endorse or promote products derived
+ *  from this software without specific prior written permission.
+ *
+ *  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+ *  "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+ *  LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+ *  A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+ *  OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ *  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+ *  LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ *  DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+ *  THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ *  (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+static CODES: &[(&str, &str)] = &[
+	("G0001", include_str!("codes/G0001.md")),
+	("G0002", include_str!("codes/G0002.md")),
+	("G0003", include_str!("codes/G0003.md")),
+	("G0004", include_str!("codes/G0004.md")),
+	("G0005", include_str!("codes/G0005.md")),
+	("G0006", include_str!("codes/G0006.md")),
+	("G0007", include_str!("codes/G0007.md")),
+	("G0008", include_str!("codes/G0008.md")),
+	("G0009", include_str!("codes/G0009.md")),
+	("G0010", include_str!("codes/G0010.md")),
+	("G0011", include_str!("codes/G0011.md")),
+	("G0012", include_str!("codes/G0012.md")),
+	("G0013", include_str!("codes/G0013.md")),
+	("G0014", include_str!("codes/G0014.md")),
+	("G0015", include_str!("codes/G0015.md")),
+	("G0016", include_str!("codes/G0016.md")),
+	("G0017", include_str!("codes/G0017.md")),
+	("G0018", include_str!("codes/G0018.md")),
+];
+
+/// Returns the long form explanation of an error code.
+pub fn explain(code: &str) -> Option<&'static str> {
+	CODES
+		.iter()
+		.find(|(c, _)| c.eq_ignore_ascii_case(code))
+		.map(|(_, text)| *text)
+}
+
+/// Returns the one line summary of an error code, such as "missing else".
+pub fn title(code: &str) -> Option<&'static str> {
+	explain(code)
+		.and_then(|text| text.lines().next())
+		.and_then(|line| line.split_once(": "))
+		.map(|(_, title)| title)
+}
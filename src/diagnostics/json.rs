@@ -0,0 +1,365 @@
+/*
+ *  Copyright (c) 2020, GiraffeKey
+ *
+ *  All rights reserved.
+ *
+ *  Redistribution and use in source and binary forms, with or without
+ *  modification, are permitted provided that the following conditions are met:
+ *
+ *  Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ *
+ *  Redistributions in binary form must reproduce the above
+ *  copyright notice, this list of conditions and the following
+ *  disclaimer in the documentation and/or other materials provided
+ *  with the distribution.
+ *
+ *  Neither the name of GiraffeKey nor the names of other
+ *  contributors may be used to endorse or promote products derived
+ *  from this software without specific prior written permission.
+ *
+ *  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+ *  "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+ *  LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+ *  A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+ *  OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ *  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+ *  LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ *  DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+ *  THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ *  (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::fmt;
+use crate::ast::Span;
+use crate::error::line_col;
+use super::codes::{explain, title};
+use super::{Diagnostic, Severity, SourceMap};
+
+/// Just enough of JSON to write diagnostics out.
+enum Json {
+	Null,
+	Bool(bool),
+	Number(usize),
+	String(String),
+	Array(Vec<Json>),
+	Object(Vec<(&'static str, Json)>),
+}
+
+impl From<&str> for Json {
+	fn from(s: &str) -> Self {
+		Json::String(s.into())
+	}
+}
+
+impl From<usize> for Json {
+	fn from(n: usize) -> Self {
+		Json::Number(n)
+	}
+}
+
+impl From<u32> for Json {
+	fn from(n: u32) -> Self {
+		Json::Number(n as usize)
+	}
+}
+
+impl fmt::Display for Json {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Json::Null => write!(f, "null"),
+			Json::Bool(b) => write!(f, "{}", b),
+			Json::Number(n) => write!(f, "{}", n),
+			Json::String(s) => {
+				write!(f, "\"")?;
+
+				for c in s.chars() {
+					match c {
+						'"' => write!(f, "\\\"")?,
+						'\\' => write!(f, "\\\\")?,
+						'\n' => write!(f, "\\n")?,
+						'\r' => write!(f, "\\r")?,
+						'\t' => write!(f, "\\t")?,
+						c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+						c => write!(f, "{}", c)?,
+					}
+				}
+
+				write!(f, "\"")
+			},
+			Json::Array(values) => {
+				write!(f, "[")?;
+
+				for (i, v) in values.iter().enumerate() {
+					if i > 0 {
+						write!(f, ",")?;
+					}
+
+					write!(f, "{}", v)?;
+				}
+
+				write!(f, "]")
+			},
+			Json::Object(fields) => {
+				write!(f, "{{")?;
+
+				for (i, (k, v)) in fields.iter().enumerate() {
+					if i > 0 {
+						write!(f, ",")?;
+					}
+
+					write!(f, "{}:{}", Json::from(*k), v)?;
+				}
+
+				write!(f, "}}")
+			},
+		}
+	}
+}
+
+fn span(span: Span, sources: &SourceMap) -> Json {
+	let input = sources.input(span.file);
+	let (line, column) = line_col(input, span.start);
+	let (end_line, end_column) = line_col(input, span.end);
+
+	Json::Object(vec![
+		("start", span.start.into()),
+		("end", span.end.into()),
+		("line", line.into()),
+		("column", column.into()),
+		("end_line", end_line.into()),
+		("end_column", end_column.into()),
+	])
+}
+
+fn code(diagnostic: &Diagnostic) -> Json {
+	diagnostic.code.as_deref().map_or(Json::Null, Json::from)
+}
+
+/// Returns a diagnostic as a single line of JSON.
+pub fn to_json(diagnostic: &Diagnostic, sources: &SourceMap) -> String {
+	let primary = diagnostic.span();
+
+	let labels = diagnostic.labels
+		.iter()
+		.map(|l| Json::Object(vec![
+			("file", sources.name(l.span.file).into()),
+			("span", span(l.span, sources)),
+			("message", l.message.as_str().into()),
+			("primary", Json::Bool(l.primary)),
+		]))
+		.collect();
+
+	let suggestions = diagnostic.suggestions
+		.iter()
+		.map(|s| Json::Object(vec![
+			("file", sources.name(s.span.file).into()),
+			("span", span(s.span, sources)),
+			("message", s.message.as_str().into()),
+			("replacement", s.replacement.as_str().into()),
+		]))
+		.collect();
+
+	Json::Object(vec![
+		("severity", diagnostic.severity.name().into()),
+		("code", code(diagnostic)),
+		("message", diagnostic.message.as_str().into()),
+		("file", primary.map_or(Json::Null, |s| sources.name(s.file).into())),
+		("span", primary.map_or(Json::Null, |s| span(s, sources))),
+		("labels", Json::Array(labels)),
+		("notes", Json::Array(diagnostic.notes.iter().map(|n| n.as_str().into()).collect())),
+		("help", Json::Array(diagnostic.help.iter().map(|h| h.as_str().into()).collect())),
+		("suggestions", Json::Array(suggestions)),
+	]).to_string()
+}
+
+fn location(span: Span, sources: &SourceMap, message: Option<&str>) -> Json {
+	let input = sources.input(span.file);
+	let (line, column) = line_col(input, span.start);
+	let (end_line, end_column) = line_col(input, span.end);
+
+	let mut fields = vec![
+		("physicalLocation", Json::Object(vec![
+			("artifactLocation", Json::Object(vec![("uri", sources.name(span.file).into())])),
+			("region", Json::Object(vec![
+				("startLine", line.into()),
+				("startColumn", column.into()),
+				("endLine", end_line.into()),
+				("endColumn", end_column.into()),
+				("byteOffset", span.start.into()),
+				("byteLength", (span.end - span.start).into()),
+			])),
+		])),
+	];
+
+	if let Some(message) = message {
+		fields.push(("message", Json::Object(vec![("text", message.into())])));
+	}
+
+	Json::Object(fields)
+}
+
+fn result(diagnostic: &Diagnostic, sources: &SourceMap) -> Json {
+	let level = match diagnostic.severity {
+		Severity::Error => "error",
+		Severity::Warning => "warning",
+	};
+
+	// SARIF has a single message, so notes and help are appended to it
+	let mut text = diagnostic.message.clone();
+
+	for note in &diagnostic.notes {
+		text += &format!("\nnote: {}", note);
+	}
+
+	for help in &diagnostic.help {
+		text += &format!("\nhelp: {}", help);
+	}
+
+	let mut fields = vec![
+		("level", level.into()),
+		("message", Json::Object(vec![("text", text.as_str().into())])),
+		("locations", Json::Array(
+			diagnostic.labels.iter().filter(|l| l.primary).map(|l| location(l.span, sources, None)).collect()
+		)),
+		("relatedLocations", Json::Array(
+			diagnostic.labels.iter().filter(|l| !l.primary).map(|l| location(l.span, sources, Some(&l.message))).collect()
+		)),
+	];
+
+	if let Some(code) = &diagnostic.code {
+		fields.insert(0, ("ruleId", code.as_str().into()));
+	}
+
+	let fixes = diagnostic.suggestions
+		.iter()
+		.map(|s| Json::Object(vec![
+			("description", Json::Object(vec![("text", s.message.as_str().into())])),
+			("artifactChanges", Json::Array(vec![Json::Object(vec![
+				("artifactLocation", Json::Object(vec![("uri", sources.name(s.span.file).into())])),
+				("replacements", Json::Array(vec![Json::Object(vec![
+					("deletedRegion", Json::Object(vec![
+						("byteOffset", s.span.start.into()),
+						("byteLength", (s.span.end - s.span.start).into()),
+					])),
+					("insertedContent", Json::Object(vec![("text", s.replacement.as_str().into())])),
+				])])),
+			])])),
+		]))
+		.collect::<Vec<_>>();
+
+	if !fixes.is_empty() {
+		fields.push(("fixes", Json::Array(fixes)));
+	}
+
+	Json::Object(fields)
+}
+
+/// Returns a SARIF 2.1.0 log holding every diagnostic of a run.
+pub fn to_sarif(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+	let mut codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+	codes.sort_unstable();
+	codes.dedup();
+
+	let rules = codes
+		.into_iter()
+		.map(|code| Json::Object(vec![
+			("id", code.into()),
+			("shortDescription", Json::Object(vec![("text", title(code).unwrap_or(code).into())])),
+			("help", Json::Object(vec![
+				("text", explain(code).unwrap_or("").into()),
+				("markdown", explain(code).unwrap_or("").into()),
+			])),
+		]))
+		.collect();
+
+	Json::Object(vec![
+		("$schema", "https://json.schemastore.org/sarif-2.1.0.json".into()),
+		("version", "2.1.0".into()),
+		("runs", Json::Array(vec![Json::Object(vec![
+			("tool", Json::Object(vec![
+				("driver", Json::Object(vec![
+					("name", "glaze".into()),
+					("version", env!("CARGO_PKG_VERSION").into()),
+					("rules", Json::Array(rules)),
+				])),
+			])),
+			("columnKind", "unicodeCodePoints".into()),
+			("results", Json::Array(diagnostics.iter().map(|d| result(d, sources)).collect())),
+		])])),
+	]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ast::FileId;
+
+	fn sources() -> (SourceMap, FileId) {
+		let mut sources = SourceMap::new();
+		let file = sources.add("dir/a \"b\".glz", b"$a = 1\n$b = \xc3\xa9 +".to_vec());
+		(sources, file)
+	}
+
+	#[test]
+	fn strings_are_escaped() {
+		let s = Json::from("\"quoted\" \\ tab\tline\nreturn\r\u{1}é");
+		assert_eq!(s.to_string(), r#""\"quoted\" \\ tab\tline\nreturn\r\u0001é""#);
+	}
+
+	#[test]
+	fn json_output() {
+		let (sources, file) = sources();
+		let diagnostic = Diagnostic::error("Unexpected end of line")
+			.with_code("G0014")
+			.with_label(Span::new(file, 16, 16), "expected more before this")
+			.with_secondary(Span::new(file, 0, 2), "")
+			.with_help("add a value")
+			.with_suggestion(Span::new(file, 16, 16), "add a value", "1");
+
+		assert_eq!(to_json(&diagnostic, &sources), concat!(
+			r#"{"severity":"error","code":"G0014","message":"Unexpected end of line","file":"dir/a \"b\".glz","#,
+			r#""span":{"start":16,"end":16,"line":2,"column":9,"end_line":2,"end_column":9},"#,
+			r#""labels":[{"file":"dir/a \"b\".glz","span":{"start":16,"end":16,"line":2,"column":9,"end_line":2,"end_column":9},"message":"expected more before this","primary":true},"#,
+			r#"{"file":"dir/a \"b\".glz","span":{"start":0,"end":2,"line":1,"column":1,"end_line":1,"end_column":3},"message":"","primary":false}],"#,
+			r#""notes":[],"help":["add a value"],"#,
+			r#""suggestions":[{"file":"dir/a \"b\".glz","span":{"start":16,"end":16,"line":2,"column":9,"end_line":2,"end_column":9},"message":"add a value","replacement":"1"}]}"#,
+		));
+	}
+
+	#[test]
+	fn json_without_a_code_or_span() {
+		let (sources, _) = sources();
+		let diagnostic = Diagnostic::warning("Nothing to do");
+
+		assert_eq!(
+			to_json(&diagnostic, &sources),
+			r#"{"severity":"warning","code":null,"message":"Nothing to do","file":null,"span":null,"labels":[],"notes":[],"help":[],"suggestions":[]}"#,
+		);
+	}
+
+	#[test]
+	fn sarif_output() {
+		let (sources, file) = sources();
+		let diagnostics = [
+			Diagnostic::error("First").with_code("G0014").with_label(Span::new(file, 12, 14), "").with_note("a note"),
+			Diagnostic::error("Second").with_code("G0014").with_suggestion(Span::new(file, 16, 16), "add", "1"),
+			Diagnostic::warning("Third").with_code("G0001"),
+		];
+		let sarif = to_sarif(&diagnostics, &sources);
+
+		assert!(sarif.starts_with(r#"{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0","runs":[{"tool":{"driver":{"name":"glaze","#));
+		// Each code is described once, in order
+		assert_eq!(sarif.matches(r#"{"id":"#).count(), 2);
+		assert!(sarif.find(r#"{"id":"G0001","shortDescription":{"text":"#).unwrap() < sarif.find(r#"{"id":"G0014""#).unwrap());
+		assert!(sarif.contains(concat!(
+			r#"{"ruleId":"G0014","level":"error","message":{"text":"First\nnote: a note"},"#,
+			r#""locations":[{"physicalLocation":{"artifactLocation":{"uri":"dir/a \"b\".glz"},"#,
+			r#""region":{"startLine":2,"startColumn":6,"endLine":2,"endColumn":7,"byteOffset":12,"byteLength":2}}}],"relatedLocations":[]}"#,
+		)));
+		assert!(sarif.contains(r#""fixes":[{"description":{"text":"add"},"artifactChanges":[{"artifactLocation":{"uri":"dir/a \"b\".glz"},"replacements":[{"deletedRegion":{"byteOffset":16,"byteLength":0},"insertedContent":{"text":"1"}}]}]}]"#));
+		assert!(sarif.contains(r#"{"ruleId":"G0001","level":"warning","message":{"text":"Third"},"locations":[],"relatedLocations":[]}"#));
+		assert!(sarif.ends_with(r#"]}]}"#));
+	}
+}
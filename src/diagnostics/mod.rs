@@ -0,0 +1,443 @@
+/*
+ *  Copyright (c) 2020, GiraffeKey
+ *
+ *  All rights reserved.
+ *
+ *  Redistribution and use in source and binary forms, with or without
+ *  modification, are permitted provided that the following conditions are met:
+ *
+ *  Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ *
+ *  Redistributions in binary form must reproduce the above
+ *  copyright notice, this list of conditions and the following
+ *  disclaimer in the documentation and/or other materials provided
+ *  with the distribution.
+ *
+ *  Neither the name of GiraffeKey nor the names of other
+ *  contributors may be used to endorse or promote products derived
+ *  from this software without specific prior written permission.
+ *
+ *  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+ *  "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+ *  LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+ *  A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+ *  OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ *  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+ *  LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ *  DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+ *  THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ *  (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+mod codes;
+mod json;
+
+pub use codes::explain;
+pub use json::{to_json, to_sarif};
+
+use std::fmt::Write;
+use crate::ast::{FileId, Span};
+use crate::error::{line_col, ErrorKind, ParseError};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+	Error,
+	Warning,
+}
+
+impl Severity {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+		}
+	}
+
+	fn color(&self) -> &'static str {
+		match self {
+			Severity::Error => RED,
+			Severity::Warning => YELLOW,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+	pub span: Span,
+	pub message: String,
+	/// Primary labels mark the cause of the diagnostic, secondary ones add
+	/// context.
+	pub primary: bool,
+}
+
+/// A fix that can be applied by replacing the text under `span`.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+	pub span: Span,
+	pub message: String,
+	pub replacement: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub code: Option<String>,
+	pub message: String,
+	pub labels: Vec<Label>,
+	pub notes: Vec<String>,
+	pub help: Vec<String>,
+	pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+	pub fn new(severity: Severity, message: &str) -> Self {
+		Self {
+			severity,
+			code: None,
+			message: message.into(),
+			labels: Vec::new(),
+			notes: Vec::new(),
+			help: Vec::new(),
+			suggestions: Vec::new(),
+		}
+	}
+
+	pub fn error(message: &str) -> Self {
+		Self::new(Severity::Error, message)
+	}
+
+	pub fn warning(message: &str) -> Self {
+		Self::new(Severity::Warning, message)
+	}
+
+	pub fn with_code(mut self, code: &str) -> Self {
+		self.code = Some(code.into());
+		self
+	}
+
+	pub fn with_label(mut self, span: Span, message: &str) -> Self {
+		self.labels.push(Label { span, message: message.into(), primary: true });
+		self
+	}
+
+	pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+		self.labels.push(Label { span, message: message.into(), primary: false });
+		self
+	}
+
+	pub fn with_note(mut self, note: &str) -> Self {
+		self.notes.push(note.into());
+		self
+	}
+
+	pub fn with_help(mut self, help: &str) -> Self {
+		self.help.push(help.into());
+		self
+	}
+
+	pub fn with_suggestion(mut self, span: Span, message: &str, replacement: &str) -> Self {
+		self.suggestions.push(Suggestion { span, message: message.into(), replacement: replacement.into() });
+		self
+	}
+
+	/// Returns the span the diagnostic is reported at.
+	pub fn span(&self) -> Option<Span> {
+		self.labels.iter().find(|l| l.primary).map(|l| l.span)
+	}
+}
+
+impl From<&ParseError> for Diagnostic {
+	fn from(error: &ParseError) -> Self {
+		let label = match error.kind {
+			ErrorKind::UnexpectedSymbol => "unexpected here",
+			ErrorKind::TrailingDot => "expected a digit after this",
+			ErrorKind::UnclosedString => "this string is never closed",
+			ErrorKind::ExpectedHex => "expected a hex digit",
+			ErrorKind::UndefinedVariable => "not found in this scope",
+			ErrorKind::AlreadyDefined => "defined again here",
+			ErrorKind::CyclicType => "refers back to the alias being defined",
+			ErrorKind::MissingElse => "this if has no else",
+			ErrorKind::NonAssociative => "needs parentheses",
+			ErrorKind::InvalidOperator => "cannot be declared",
+			ErrorKind::UnknownUnit => "not a CSS unit",
+			ErrorKind::UnclosedComment => "this comment is never closed",
+			ErrorKind::InconsistentIndent => "indented differently from the rest of the file",
+			ErrorKind::UnexpectedEnd => "expected more before this",
+			ErrorKind::MissingBody => "nothing is indented under this",
+			ErrorKind::UnclosedBracket => "this bracket is never closed",
+			ErrorKind::UnexpectedIndent => "indented deeper than the block it is in",
+			ErrorKind::MisplacedData => "not in a selector or event block",
+		};
+
+		let mut diagnostic = Diagnostic::error(&error.message)
+			.with_code(error.kind.code())
+			.with_label(error.span, label);
+
+		for (span, message) in &error.labels {
+			diagnostic = diagnostic.with_secondary(*span, message);
+		}
+
+		for (span, message, replacement) in &error.suggestions {
+			diagnostic = diagnostic.with_suggestion(*span, message, replacement);
+		}
+
+		match error.kind {
+			ErrorKind::TrailingDot => {
+				let end = Span::new(error.span.file, error.span.end, error.span.end);
+				diagnostic.with_suggestion(end, "add a digit after the dot", "0")
+			},
+			ErrorKind::UnclosedString => {
+				let end = Span::new(error.span.file, error.span.end, error.span.end);
+				diagnostic.with_suggestion(end, "close the string", "\"")
+			},
+			ErrorKind::UnclosedComment => {
+				let end = Span::new(error.span.file, error.span.end, error.span.end);
+				diagnostic.with_suggestion(end, "close the comment", "*/")
+			},
+			ErrorKind::ExpectedHex => diagnostic.with_note("colors are written as `#` followed by hex digits, such as `#fa0`"),
+			ErrorKind::UndefinedVariable => diagnostic.with_help("define it first with `$name = value`"),
+			ErrorKind::CyclicType => diagnostic.with_note("an alias cannot be defined in terms of itself, even through other aliases"),
+			ErrorKind::MissingElse => diagnostic.with_help("add an `else` branch so there is a value in every case"),
+			ErrorKind::NonAssociative => diagnostic.with_help("add parentheses to show which operator applies first"),
+			ErrorKind::MissingBody => diagnostic.with_help("indent what belongs to it on the lines below it"),
+			ErrorKind::UnclosedBracket => diagnostic.with_help("close it on the same line, or indent the lines that continue it"),
+			ErrorKind::MisplacedData => diagnostic.with_help("move it into the block of the selector it belongs to"),
+			ErrorKind::UnexpectedIndent => diagnostic.with_help("only the lines under a selector, function or block are indented deeper"),
+			ErrorKind::InconsistentIndent => diagnostic.with_note("the first indented line decides whether the file indents with tabs, 2 or 4 spaces"),
+			_ => diagnostic,
+		}
+	}
+}
+
+/// The source files that spans point into.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+	files: Vec<(String, Vec<u8>)>,
+}
+
+impl SourceMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn add(&mut self, name: &str, input: Vec<u8>) -> FileId {
+		self.files.push((name.into(), input));
+		FileId(self.files.len() as u32 - 1)
+	}
+
+	pub fn name(&self, file: FileId) -> &str {
+		&self.files[file.0 as usize].0
+	}
+
+	pub fn input(&self, file: FileId) -> &[u8] {
+		&self.files[file.0 as usize].1
+	}
+}
+
+/// Returns the line of `input` containing `offset`, without its line break,
+/// along with the offset it starts at.
+fn line_at(input: &[u8], offset: usize) -> (&[u8], usize) {
+	let offset = offset.min(input.len());
+	let start = match input[..offset].iter().rposition(|c| *c == b'\n') {
+		Some(n) => n + 1,
+		None if input.starts_with(b"\xef\xbb\xbf") => 3.min(offset),
+		None => 0,
+	};
+	let end = input[offset..].iter().position(|c| *c == b'\n').map_or(input.len(), |n| offset + n);
+	let line = &input[start..end];
+
+	(line.strip_suffix(b"\r").unwrap_or(line), start)
+}
+
+/// Returns roughly how many terminal columns a character takes up. Wide CJK
+/// characters take two and combining marks, such as Arabic vowels, none.
+fn char_width(c: char) -> usize {
+	match c as u32 {
+		0x09 => 4,
+		0x0300..=0x036f | 0x0483..=0x0489 | 0x0591..=0x05bd | 0x0610..=0x061a
+			| 0x064b..=0x065f | 0x0670 | 0x06d6..=0x06dc | 0x06df..=0x06e4
+			| 0x06e7..=0x06e8 | 0x06ea..=0x06ed | 0x200b..=0x200f | 0x20d0..=0x20ff
+			| 0xfe00..=0xfe0f => 0,
+		0x1100..=0x115f | 0x2e80..=0x303e | 0x3041..=0x33ff | 0x3400..=0x4dbf
+			| 0x4e00..=0x9fff | 0xa000..=0xa4cf | 0xac00..=0xd7a3 | 0xf900..=0xfaff
+			| 0xfe30..=0xfe4f | 0xff00..=0xff60 | 0xffe0..=0xffe6 | 0x1f300..=0x1f64f
+			| 0x1f900..=0x1f9ff | 0x20000..=0x3fffd => 2,
+		_ => 1,
+	}
+}
+
+/// Returns how many columns `bytes` take up once tabs are expanded.
+fn width(bytes: &[u8]) -> usize {
+	String::from_utf8_lossy(bytes).chars().map(char_width).sum()
+}
+
+struct Style {
+	color: bool,
+}
+
+impl Style {
+	fn paint(&self, color: &str, text: &str) -> String {
+		if self.color {
+			format!("{}{}{}", color, text, RESET)
+		} else {
+			text.into()
+		}
+	}
+}
+
+/// Renders a diagnostic the way it is shown on a terminal, with the source
+/// line of each label underlined.
+pub fn render(diagnostic: &Diagnostic, sources: &SourceMap, color: bool) -> String {
+	let style = Style { color };
+	let severity = diagnostic.severity;
+	let mut out = String::new();
+
+	let name = match &diagnostic.code {
+		Some(code) => format!("{}[{}]", severity.name(), code),
+		None => severity.name().into(),
+	};
+
+	writeln!(
+		out,
+		"{}{}",
+		style.paint(severity.color(), &name),
+		style.paint(BOLD, &format!(": {}", diagnostic.message)),
+	).unwrap();
+
+	// Each line is shown once, with the labels on it underlined in order
+	let mut lines: Vec<(FileId, u32, usize, Vec<&Label>)> = Vec::new();
+
+	for label in &diagnostic.labels {
+		let input = sources.input(label.span.file);
+		let (line, _) = line_col(input, label.span.start);
+
+		match lines.iter_mut().find(|l| l.0 == label.span.file && l.1 == line) {
+			Some(l) => l.3.push(label),
+			None => lines.push((label.span.file, line, label.span.start, vec![label])),
+		}
+	}
+
+	// The file of the primary label comes first, then lines in source order
+	let primary = diagnostic.span();
+	let main_file = primary.map(|s| s.file);
+	lines.sort_by_key(|l| (Some(l.0) != main_file, l.0 .0, l.1));
+
+	let gutter = lines.iter().map(|l| l.1.to_string().len()).max().unwrap_or(1);
+	let pipe = style.paint(BLUE, &format!("{} |", " ".repeat(gutter)));
+	let mut file = None;
+
+	for (id, number, offset, labels) in &lines {
+		let input = sources.input(*id);
+
+		if file != Some(*id) {
+			let at = match primary {
+				Some(span) if span.file == *id => span.start,
+				_ => labels[0].span.start,
+			};
+			let (line, col) = line_col(input, at);
+			let arrow = style.paint(BLUE, &format!("{}-->", " ".repeat(gutter)));
+			writeln!(out, "{} {}:{}:{}", arrow, sources.name(*id), line, col).unwrap();
+			writeln!(out, "{}", pipe).unwrap();
+			file = Some(*id);
+		}
+
+		let (line, start) = line_at(input, *offset);
+		let text = String::from_utf8_lossy(line).replace('\t', "    ");
+		writeln!(out, "{} {}", style.paint(BLUE, &format!("{:>1$} |", number, gutter)), text).unwrap();
+
+		let mut labels = labels.clone();
+		labels.sort_by_key(|l| l.span.start);
+
+		for label in labels {
+			// Spans running past the end of the line are cut off there
+			let from = label.span.start.saturating_sub(start).min(line.len());
+			let to = (label.span.end.saturating_sub(start)).clamp(from, line.len());
+			let (mark, color) = if label.primary { ("^", severity.color()) } else { ("-", BLUE) };
+			let underline = mark.repeat(width(&line[from..to]).max(1));
+			let message = if label.message.is_empty() { underline } else { format!("{} {}", underline, label.message) };
+
+			writeln!(out, "{} {}{}", pipe, " ".repeat(width(&line[..from])), style.paint(color, &message)).unwrap();
+		}
+	}
+
+	let footer = !diagnostic.notes.is_empty() || !diagnostic.help.is_empty() || !diagnostic.suggestions.is_empty();
+
+	if footer && !lines.is_empty() {
+		writeln!(out, "{}", pipe).unwrap();
+	}
+
+	for note in &diagnostic.notes {
+		writeln!(out, "{} {} note: {}", " ".repeat(gutter), style.paint(BLUE, "="), note).unwrap();
+	}
+
+	for help in &diagnostic.help {
+		writeln!(out, "{} {} help: {}", " ".repeat(gutter), style.paint(BLUE, "="), help).unwrap();
+	}
+
+	for suggestion in &diagnostic.suggestions {
+		let help = format!("{}: `{}`", suggestion.message, suggestion.replacement);
+		writeln!(out, "{} {} help: {}", " ".repeat(gutter), style.paint(BLUE, "="), help).unwrap();
+	}
+
+	out
+}
+
+/// Writes a rendered diagnostic to stderr.
+pub fn emit(diagnostic: &Diagnostic, sources: &SourceMap, color: bool) {
+	eprintln!("{}", render(diagnostic, sources, color));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Renders an error with one label on `input`, from `start` to `end`.
+	fn render_at(input: &str, start: usize, end: usize) -> String {
+		let mut sources = SourceMap::new();
+		let file = sources.add("a.glz", input.as_bytes().to_vec());
+		let diagnostic = Diagnostic::error("Bad").with_code("G0001").with_label(Span::new(file, start, end), "here");
+
+		render(&diagnostic, &sources, false)
+	}
+
+	#[test]
+	fn carets_line_up_with_tabs() {
+		assert_eq!(render_at(".a\n\t\tcolor: red", 5, 10), [
+			"error[G0001]: Bad",
+			" --> a.glz:2:3",
+			"  |",
+			"2 |         color: red",
+			"  |         ^^^^^ here",
+			"",
+		].join("\n"));
+	}
+
+	#[test]
+	fn carets_line_up_with_wide_characters() {
+		let input = "$a = \"漢字\" ++ b";
+		let start = input.find("++").unwrap();
+
+		assert_eq!(render_at(input, 5, start - 1), [
+			"error[G0001]: Bad",
+			" --> a.glz:1:6",
+			"  |",
+			"1 | $a = \"漢字\" ++ b",
+			"  |      ^^^^^^ here",
+			"",
+		].join("\n"));
+		assert!(render_at(input, start, start + 2).ends_with("\n  |             ^^ here\n"));
+	}
+
+	#[test]
+	fn empty_spans_get_one_caret() {
+		assert!(render_at("$a = ", 5, 5).ends_with("\n  |      ^ here\n"));
+	}
+}
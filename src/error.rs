@@ -31,6 +31,140 @@
  *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 
-pub fn throw_error(error: &str, position: (u32, u32)) {
-	println!("Line {}, Column {}: {}", position.0, position.1, error);
+use crate::ast::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+	UnexpectedSymbol,
+	TrailingDot,
+	UnclosedString,
+	ExpectedHex,
+	UndefinedVariable,
+	AlreadyDefined,
+	CyclicType,
+	MissingElse,
+	NonAssociative,
+	InvalidOperator,
+	UnknownUnit,
+	UnclosedComment,
+	InconsistentIndent,
+	UnexpectedEnd,
+	MissingBody,
+	UnclosedBracket,
+	UnexpectedIndent,
+	MisplacedData,
+}
+
+impl ErrorKind {
+	/// Returns the stable code of the error. Codes are never reused, so new
+	/// kinds always take the next free number.
+	pub fn code(&self) -> &'static str {
+		match self {
+			ErrorKind::UnexpectedSymbol => "G0001",
+			ErrorKind::TrailingDot => "G0002",
+			ErrorKind::UnclosedString => "G0003",
+			ErrorKind::ExpectedHex => "G0004",
+			ErrorKind::UndefinedVariable => "G0005",
+			ErrorKind::AlreadyDefined => "G0006",
+			ErrorKind::CyclicType => "G0007",
+			ErrorKind::MissingElse => "G0008",
+			ErrorKind::NonAssociative => "G0009",
+			ErrorKind::InvalidOperator => "G0010",
+			ErrorKind::UnknownUnit => "G0011",
+			ErrorKind::UnclosedComment => "G0012",
+			ErrorKind::InconsistentIndent => "G0013",
+			ErrorKind::UnexpectedEnd => "G0014",
+			ErrorKind::MissingBody => "G0015",
+			ErrorKind::UnclosedBracket => "G0016",
+			ErrorKind::UnexpectedIndent => "G0017",
+			ErrorKind::MisplacedData => "G0018",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+	pub kind: ErrorKind,
+	pub message: String,
+	pub span: Span,
+	/// Other places in the source that help explain the error.
+	pub labels: Vec<(Span, String)>,
+	/// Fixes as a message and the text to replace the span with.
+	pub suggestions: Vec<(Span, String, String)>,
+}
+
+impl ParseError {
+	pub fn new(kind: ErrorKind, message: &str, span: Span) -> Self {
+		Self {
+			kind,
+			message: message.into(),
+			span,
+			labels: Vec::new(),
+			suggestions: Vec::new(),
+		}
+	}
+
+	pub fn with_label(mut self, span: Span, message: &str) -> Self {
+		self.labels.push((span, message.into()));
+		self
+	}
+
+	pub fn with_suggestion(mut self, span: Span, message: &str, replacement: &str) -> Self {
+		self.suggestions.push((span, message.into(), replacement.into()));
+		self
+	}
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// Returns the line and column of a byte offset, both starting from 1.
+pub fn line_col(input: &[u8], offset: usize) -> (u32, u32) {
+	let mut line = 1;
+	let mut col = 1;
+	let bom = if input.starts_with(b"\xef\xbb\xbf") { 3 } else { 0 };
+
+	for c in input.iter().take(offset).skip(bom) {
+		if *c == b'\n' {
+			line += 1;
+			col = 1;
+		} else if *c & 0xc0 != 0x80 {
+			// Columns count characters, so UTF-8 continuation bytes are skipped
+			col += 1;
+		}
+	}
+
+	(line, col)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lines_and_columns_start_at_one() {
+		let input = b"ab\ncd\r\nef";
+
+		assert_eq!(line_col(input, 0), (1, 1));
+		assert_eq!(line_col(input, 2), (1, 3));
+		assert_eq!(line_col(input, 3), (2, 1));
+		assert_eq!(line_col(input, 7), (3, 1));
+		assert_eq!(line_col(input, 100), (3, 3));
+	}
+
+	#[test]
+	fn columns_count_characters() {
+		let input = "é漢 x\n👍y".as_bytes();
+
+		assert_eq!(line_col(input, "é漢 ".len()), (1, 4));
+		assert_eq!(line_col(input, "é漢 x\n👍".len()), (2, 2));
+	}
+
+	#[test]
+	fn byte_order_marks_take_no_column() {
+		let input = "\u{feff}.a".as_bytes();
+
+		assert_eq!(line_col(input, 0), (1, 1));
+		assert_eq!(line_col(input, 3), (1, 1));
+		assert_eq!(line_col(input, 4), (1, 2));
+	}
 }
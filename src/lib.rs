@@ -32,5 +32,6 @@
  */
 
 pub mod error;
+pub mod diagnostics;
 pub mod ast;
 pub mod parser;
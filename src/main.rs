@@ -31,16 +31,117 @@
  *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 
+use std::env;
 use std::fs;
+use std::io::{self, IsTerminal};
+use std::process::exit;
+use glaze::diagnostics::{emit, explain, to_json, to_sarif, Diagnostic, SourceMap};
+use glaze::parser::parse;
 
-mod error;
-mod ast;
-mod parser;
+enum MessageFormat {
+    Human,
+    Json,
+    Sarif,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: glaze [--message-format human|json|sarif] [file]");
+    eprintln!("       glaze explain <code>");
+    exit(2);
+}
 
-//use parser::parse;
+fn explain_code(code: &str) -> ! {
+    match explain(code) {
+        Some(text) => {
+            print!("{}", text);
+            exit(0);
+        },
+        None => {
+            eprintln!("error: {} is not a Glaze error code", code);
+            exit(1);
+        },
+    }
+}
 
 fn main() {
-    let input = fs::read("example.glz").unwrap();
+    let mut format = MessageFormat::Human;
+    let mut path = "example.glz".to_owned();
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().map(String::as_str) == Some("explain") {
+        args.next();
+
+        match (args.next(), args.next()) {
+            (Some(code), None) => explain_code(&code),
+            _ => usage(),
+        }
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--message-format" => {
+                format = match args.next().as_deref() {
+                    Some("human") => MessageFormat::Human,
+                    Some("json") => MessageFormat::Json,
+                    Some("sarif") => MessageFormat::Sarif,
+                    _ => usage(),
+                };
+            },
+            _ if arg.starts_with('-') => usage(),
+            _ => path = arg,
+        }
+    }
+
+    let mut sources = SourceMap::new();
+    let input = match fs::read(&path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", path, e);
+            exit(1);
+        },
+    };
+    let file = sources.add(&path, input);
+
+    let (ast, errors) = match parse(sources.input(file), file) {
+        Ok(ast) => (Some(ast), Vec::new()),
+        Err(errors) => (None, errors),
+    };
+    let diagnostics: Vec<Diagnostic> = errors.iter().map(Diagnostic::from).collect();
+
+    match format {
+        MessageFormat::Human => {
+            // Color is only used on a terminal, and never when NO_COLOR is set
+            let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+            for diagnostic in &diagnostics {
+                emit(diagnostic, &sources, color);
+            }
+
+            let mut codes: Vec<&str> = diagnostics.iter().filter_map(|d| d.code.as_deref()).collect();
+            codes.sort_unstable();
+            codes.dedup();
+
+            if codes.len() > 1 {
+                eprintln!("Some errors have detailed explanations: {}.", codes.join(", "));
+            }
+
+            if let Some(code) = codes.first() {
+                eprintln!("For more information about an error, try `glaze explain {}`.", code);
+            }
+
+            if let Some(ast) = ast {
+                println!("{:#?}", ast);
+            }
+        },
+        MessageFormat::Json => {
+            for diagnostic in &diagnostics {
+                println!("{}", to_json(diagnostic, &sources));
+            }
+        },
+        MessageFormat::Sarif => println!("{}", to_sarif(&diagnostics, &sources)),
+    }
 
-    println!("{:#?}", parser::parse(&input));
+    if !diagnostics.is_empty() {
+        exit(1);
+    }
 }
@@ -31,7 +31,11 @@
  *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 
-use crate::error::throw_error;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use crate::ast::{Comment, FileId, Span};
+use crate::error::{ErrorKind, ParseError, ParseResult};
 
 static WHITESPACE: &[u8] = b" \t\n\r";
 static DIGITS: &[u8] = b"0123456789";
@@ -39,22 +43,82 @@ static HEX: &[u8] = b"0123456789abcdefABCDEF";
 static ALPHA: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
 static SYMBOL: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_-";
 
+/// Every symbolic operator. Matching always picks the longest one, so `++` is
+/// never read as two `+`.
+pub static OPERATORS: &[&str] = &[
+	"->", "|>", "::", "==", "!=", "<=", ">=", "++",
+	"+", "-", "*", "/", "<", ">",
+];
+
+/// Every CSS unit, in lowercase since units are not case sensitive.
+static UNITS: &[&str] = &[
+	"%",
+	"px", "cm", "mm", "q", "in", "pc", "pt",
+	"em", "rem", "ex", "rex", "ch", "rch", "cap", "rcap", "ic", "ric", "lh", "rlh",
+	"vw", "vh", "vi", "vb", "vmin", "vmax",
+	"svw", "svh", "svi", "svb", "svmin", "svmax",
+	"lvw", "lvh", "lvi", "lvb", "lvmin", "lvmax",
+	"dvw", "dvh", "dvi", "dvb", "dvmin", "dvmax",
+	"cqw", "cqh", "cqi", "cqb", "cqmin", "cqmax",
+	"deg", "grad", "rad", "turn",
+	"s", "ms", "hz", "khz",
+	"dpi", "dpcm", "dppx", "x",
+	"fr",
+];
+
+/// Words that cannot be used as names. `and` and `or` are operators instead.
+pub static KEYWORDS: &[&[u8]] = &[b"enum", b"type", b"if", b"else", b"not", b"infixl", b"infixr", b"infix"];
+
+/// Characters that custom operators are made of.
+static OPERATOR_CHARS: &[u8] = b"+-*/<>=!?^|%&~";
+
+/// What one level of indentation is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentUnit {
+	Tabs,
+	Spaces(usize),
+}
+
+impl fmt::Display for IndentUnit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			IndentUnit::Tabs => write!(f, "tabs"),
+			IndentUnit::Spaces(n) => write!(f, "{} spaces", n),
+		}
+	}
+}
+
+/// The comments a lexer has stepped over. They are shared between the clones
+/// of a lexer, so comments read while backtracking are kept.
+#[derive(Default)]
+struct Comments {
+	found: Vec<Comment>,
+	unclosed: Option<ParseError>,
+}
+
+#[derive(Clone)]
 pub struct Lexer<'a> {
 	input: &'a [u8],
+	file: FileId,
 	position: usize,
 	line: u32,
 	col: u32,
-	binary_ops: &'a [&'a [u8]],
+	/// The indentation of the file, taken from its first indented line.
+	indent: Option<(IndentUnit, Span)>,
+	comments: Rc<RefCell<Comments>>,
 }
 
 impl<'a> Lexer<'a> {
-	pub fn new(input: &'a [u8]) -> Self {
+	pub fn new(input: &'a [u8], file: FileId) -> Self {
 		Self {
 			input,
-			position: 0,
+			file,
+			// Skip the byte order mark some editors write at the start
+			position: if input.starts_with(b"\xef\xbb\xbf") { 3 } else { 0 },
 			line: 1,
 			col: 1,
-			binary_ops: &[b"*", b"/", b"+", b"-", b"++", b"."],
+			indent: None,
+			comments: Rc::default(),
 		}
 	}
 
@@ -66,6 +130,20 @@ impl<'a> Lexer<'a> {
 		(self.line, self.col)
 	}
 
+	pub fn offset(&self) -> usize {
+		self.position
+	}
+
+	pub fn span_at(&self, n: usize, len: usize) -> Span {
+		let start = (self.position + n).min(self.input.len());
+		Span::new(self.file, start, (start + len).min(self.input.len()))
+	}
+
+	/// Returns the span from `start` up to the current position.
+	pub fn span_from(&self, start: usize) -> Span {
+		Span::new(self.file, start, self.position)
+	}
+
 	pub fn position_at(&self, n: usize) -> (u32, u32) {
 		let mut line = self.line;
 		let mut col = self.col;
@@ -78,7 +156,7 @@ impl<'a> Lexer<'a> {
 			} else if next == b'\n' {
 				line += 1;
 				col = 1;
-			} else {
+			} else if !is_continuation(next) {
 				col += 1;
 			}
 		}
@@ -94,8 +172,33 @@ impl<'a> Lexer<'a> {
 		}
 	}
 
+	/// Returns the number of bytes in the UTF-8 character `n` bytes ahead.
+	pub fn char_len(&self, n: usize) -> usize {
+		let len = match self.at(n) {
+			0xc0..=0xdf => 2,
+			0xe0..=0xef => 3,
+			0xf0..=0xf7 => 4,
+			_ => 1,
+		};
+
+		// Malformed characters are skipped a byte at a time
+		if (1..len).all(|i| is_continuation(self.at(n + i))) {
+			len
+		} else {
+			1
+		}
+	}
+
+	/// Decodes the UTF-8 character `n` bytes ahead, which is `\0` past the end
+	/// of the input.
 	pub fn char_at(&self, n: usize) -> char {
-		self.at(n).into()
+		let start = (self.position + n).min(self.input.len());
+		let end = (start + self.char_len(n)).min(self.input.len());
+
+		match std::str::from_utf8(&self.input[start..end]) {
+			Ok(s) => s.chars().next().unwrap_or('\0'),
+			Err(_) => char::REPLACEMENT_CHARACTER,
+		}
 	}
 
 	pub fn peek(&self, needle: &[u8]) -> bool {
@@ -119,7 +222,7 @@ impl<'a> Lexer<'a> {
 			} else if next == b'\n' {
 				self.line += 1;
 				self.col = 1;
-			} else {
+			} else if !is_continuation(next) {
 				self.col += 1;
 			}
 
@@ -132,9 +235,123 @@ impl<'a> Lexer<'a> {
 	}
 
 	pub fn skip_whitespace(&mut self) {
-		while self.is_whitespace(&self.at(0)) {
-			self.advance(1);
+		loop {
+			if self.is_whitespace(&self.at(0)) {
+				self.advance(1);
+			} else if self.comment_len(0) > 0 {
+				self.skip_blank(self.comment_len(0));
+			} else {
+				break;
+			}
+		}
+	}
+
+	pub fn skip_spaces(&mut self) {
+		self.skip_blank(self.spaces_len(0));
+	}
+
+	/// Moves past `n` bytes of whitespace and comments, keeping the comments
+	/// to be attached to nodes later.
+	pub fn skip_blank(&mut self, n: usize) {
+		let end = self.position + n;
+
+		while self.position < end && self.has_left() {
+			let len = self.comment_len(0);
+
+			if len > 0 {
+				self.keep_comment(len);
+				self.advance(len);
+			} else {
+				self.advance(1);
+			}
+		}
+	}
+
+	fn keep_comment(&self, len: usize) {
+		let mut comments = self.comments.borrow_mut();
+
+		match self.read_comment(len) {
+			// Backtracking may step over the same comment twice
+			Ok(c) => if let Err(i) = comments.found.binary_search_by_key(&c.span.start, |c| c.span.start) {
+				comments.found.insert(i, c);
+			},
+			Err(e) => comments.unclosed = Some(e),
+		}
+	}
+
+	/// Takes the comments stepped over so far in order, along with the error
+	/// for a comment left open.
+	pub fn take_comments(&self) -> (Vec<Comment>, Option<ParseError>) {
+		let comments = self.comments.take();
+		(comments.found, comments.unclosed)
+	}
+
+	/// Returns the number of bytes of spaces, tabs and comments `n` bytes
+	/// ahead.
+	pub fn spaces_len(&self, mut n: usize) -> usize {
+		let start = n;
+
+		loop {
+			if [b' ', b'\t'].contains(&self.at(n)) {
+				n += 1;
+			} else if self.comment_len(n) > 0 {
+				n += self.comment_len(n);
+			} else {
+				return n - start;
+			}
+		}
+	}
+
+	/// Returns the length of the comment `n` bytes ahead, or 0 if there is
+	/// none. An unclosed block comment runs to the end of the input.
+	pub fn comment_len(&self, n: usize) -> usize {
+		let mut len = 2;
+
+		if self.peek_at(n, b"//") {
+			while ![b'\n', b'\r', 0].contains(&self.at(n + len)) {
+				len += 1;
+			}
+
+			len
+		} else if self.peek_at(n, b"/*") {
+			while self.at(n + len) != 0 && !self.peek_at(n + len, b"*/") {
+				len += 1;
+			}
+
+			if self.at(n + len) == 0 {
+				len
+			} else {
+				len + 2
+			}
+		} else {
+			0
+		}
+	}
+
+	pub fn try_comment(&mut self) -> ParseResult<Option<Comment>> {
+		let len = self.comment_len(0);
+
+		if len == 0 {
+			return Ok(None);
+		}
+
+		let comment = self.read_comment(len)?;
+		self.advance(len);
+		Ok(Some(comment))
+	}
+
+	/// Reads the comment of `len` bytes here without moving past it.
+	fn read_comment(&self, len: usize) -> ParseResult<Comment> {
+		let block = self.at(1) == b'*';
+		let span = self.span_at(0, len);
+
+		if block && (len < 4 || !self.peek_at(len - 2, b"*/")) {
+			return Err(ParseError::new(ErrorKind::UnclosedComment, "/* not closed", span));
 		}
+
+		let end = if block { len - 2 } else { len };
+		let text = String::from_utf8_lossy(&self.input[self.position + 2..self.position + end]).into_owned();
+		Ok(Comment { text, block, span })
 	}
 
 	pub fn try_whitespace(&mut self) -> bool {
@@ -150,7 +367,7 @@ impl<'a> Lexer<'a> {
 
 	pub fn try_char(&mut self, c: char) -> bool {
 		if self.char_at(0) == c {
-			self.advance(1);
+			self.advance(c.len_utf8());
 			true
 		} else {
 			false
@@ -166,48 +383,191 @@ impl<'a> Lexer<'a> {
 		}
 	}
 
-	pub fn try_newline(&mut self) -> bool {
-		let mut n = 0;
-
-		while [b' ', b'\t'].contains(&self.at(n)) {
-			n += 1;
+	pub fn try_keyword(&mut self, keyword: &[u8]) -> bool {
+		if self.peek(keyword) && !SYMBOL.contains(&self.at(keyword.len())) {
+			self.advance(keyword.len());
+			true
+		} else {
+			false
 		}
+	}
+
+	pub fn try_newline(&mut self) -> bool {
+		let n = self.spaces_len(0);
 
-		if [b'\n', b'\r'].contains(&self.at(n)) {
-			self.advance(n + 1);
+		if self.peek_at(n, b"\r\n") {
+			self.skip_blank(n);
+			self.advance(2);
+			true
+		} else if [b'\n', b'\r'].contains(&self.at(n)) {
+			self.skip_blank(n);
+			self.advance(1);
 			true
 		} else {
 			false
 		}
 	}
 
-	pub fn try_indent(&mut self, indent: usize) -> bool {
-		let mut n = 0;
+	/// Skips the rest of the line and its line break. Strings and comments
+	/// are stepped over whole, so that a comment running over several lines
+	/// is not read as code.
+	pub fn skip_line(&mut self) {
+		while self.has_left() && self.at(0) != b'\n' {
+			if self.comment_len(0) > 0 {
+				self.skip_blank(self.comment_len(0));
+			} else if let Ok(None) | Err(_) = self.try_string() {
+				self.advance(self.char_len(0));
+			}
+		}
 
-		while self.at(n) == b'\t' {
+		self.advance(1);
+	}
+
+	/// Returns the indentation level of the line starting here, or `None` if
+	/// the line is blank or only holds comments.
+	pub fn peek_indent(&self) -> ParseResult<Option<usize>> {
+		Ok(self.measure_indent()?.map(|(level, _, _)| level))
+	}
+
+	/// Skips the indentation of the line starting here, returning its level.
+	/// The first indented line decides the unit for the rest of the file.
+	pub fn skip_indent(&mut self) -> ParseResult<Option<usize>> {
+		match self.measure_indent()? {
+			Some((level, len, unit)) => {
+				if len > 0 && self.indent.is_none() {
+					self.indent = Some((unit, self.span_at(0, len)));
+				}
+
+				self.advance(len);
+				Ok(Some(level))
+			},
+			None => Ok(None),
+		}
+	}
+
+	pub fn try_indent(&mut self, depth: usize) -> ParseResult<bool> {
+		let start = self.clone();
+
+		if self.skip_indent()? == Some(depth) {
+			Ok(true)
+		} else {
+			*self = start;
+			Ok(false)
+		}
+	}
+
+	/// Returns where the first thing on the next line that is not blank or only
+	/// comments is, along with the width of that line's indentation.
+	pub fn peek_next_line(&self) -> Option<(usize, usize)> {
+		let mut n = self.spaces_len(0);
+
+		while ![b'\n', b'\r', 0].contains(&self.at(n)) {
 			n += 1;
 		}
 
-		if n == indent {
-			self.advance(n);
-			return true;
+		loop {
+			match self.at(n) {
+				b'\r' if self.at(n + 1) == b'\n' => n += 2,
+				b'\n' | b'\r' => n += 1,
+				_ => return None,
+			}
+
+			let start = n;
+
+			while [b' ', b'\t'].contains(&self.at(n)) {
+				n += 1;
+			}
+
+			let width = n - start;
+			n += self.spaces_len(n);
+
+			if ![b'\n', b'\r'].contains(&self.at(n)) {
+				return if self.at(n) == 0 { None } else { Some((n, width)) };
+			}
+		}
+	}
+
+	/// Checks whether the next line is indented deeper than `depth` levels,
+	/// making it the block of this one.
+	pub fn has_block(&self, depth: usize) -> bool {
+		matches!(self.peek_next_line(), Some((_, width)) if width > depth * self.indent_width())
+	}
+
+	/// Skips spaces and comments, and then a line break if the line after it
+	/// continues this one: it is indented deeper than `depth` levels, or is
+	/// at `depth` and starts with a comma or by closing a bracket.
+	pub fn skip_continuation(&mut self, depth: usize) {
+		self.skip_spaces();
+
+		if ![b'\n', b'\r'].contains(&self.at(0)) {
+			return;
+		}
+
+		if let Some((n, width)) = self.peek_next_line() {
+			let level = depth * self.indent_width();
+
+			if width > level || width == level && [b',', b')', b']', b'}'].contains(&self.at(n)) {
+				self.skip_blank(n);
+			}
 		}
+	}
+
+	/// Returns the width of one level of indentation, counting a tab as one.
+	fn indent_width(&self) -> usize {
+		match self.indent {
+			Some((IndentUnit::Spaces(width), _)) => width,
+			_ => 1,
+		}
+	}
 
-		n = 0;
+	/// Returns the level and length of the indentation here, along with the
+	/// unit it was measured in.
+	fn measure_indent(&self) -> ParseResult<Option<(usize, usize, IndentUnit)>> {
+		let mut n = 0;
 
-		while self.peek_at(n, b"    ") {
+		while [b' ', b'\t'].contains(&self.at(n)) {
 			n += 1;
 		}
 
-		if n == indent {
-			self.advance(n * 4);
-			true
-		} else {
-			false
+		if [b'\n', b'\r', 0].contains(&self.at(n + self.spaces_len(n))) {
+			return Ok(None);
+		}
+
+		let white = &self.input[self.position..self.position + n];
+		let tabs = white.iter().all(|c| *c == b'\t');
+		let spaces = white.iter().all(|c| *c == b' ');
+
+		let error = |message: &str| ParseError::new(ErrorKind::InconsistentIndent, message, self.span_at(0, n));
+
+		if !tabs && !spaces {
+			return Err(error("Indentation mixes tabs and spaces"));
 		}
+
+		let unit = match self.indent {
+			Some((unit, _)) => unit,
+			None if n == 0 || tabs => IndentUnit::Tabs,
+			None if n == 2 || n == 4 => IndentUnit::Spaces(n),
+			None => return Err(error("Indentation must be a tab, 2 or 4 spaces")),
+		};
+
+		let level = match unit {
+			IndentUnit::Tabs if tabs => n,
+			IndentUnit::Spaces(width) if spaces && n % width == 0 => n / width,
+			_ => {
+				let mut error = error(&format!("Expected indentation with {}", unit));
+
+				if let Some((_, span)) = self.indent {
+					error = error.with_label(span, &format!("first indented with {} here", unit));
+				}
+
+				return Err(error);
+			},
+		};
+
+		Ok(Some((level, n, unit)))
 	}
 
-	pub fn try_number(&mut self) -> Option<f32> {
+	pub fn try_number(&mut self) -> ParseResult<Option<f32>> {
 		let mut n = 0;
 		let mut s = "".to_owned();
 
@@ -216,8 +576,9 @@ impl<'a> Lexer<'a> {
 			n += 1;
 		}
 
-		if n == 0 && self.at(0) != b'.' {
-			return None;
+		// A leading dot only starts a number if a digit follows, as in `.5`
+		if n == 0 && !(self.at(0) == b'.' && DIGITS.contains(&self.at(1))) {
+			return Ok(None);
 		}
 
 		if self.at(n) == b'.' {
@@ -225,8 +586,7 @@ impl<'a> Lexer<'a> {
 			n += 1;
 
 			if !DIGITS.contains(&self.at(n)) {
-				throw_error("Trailing . not allowed", self.position_at(n));
-				return None;
+				return Err(ParseError::new(ErrorKind::TrailingDot, "Trailing . not allowed", self.span_at(n - 1, 1)));
 			}
 
 			while DIGITS.contains(&self.at(n)) {
@@ -234,36 +594,197 @@ impl<'a> Lexer<'a> {
 				n += 1;
 			}
 		}
-		
+
+		// An exponent needs digits, so `1em` is still a unit
+		if [b'e', b'E'].contains(&self.at(n)) {
+			let sign = [b'+', b'-'].contains(&self.at(n + 1)) as usize;
+
+			if DIGITS.contains(&self.at(n + 1 + sign)) {
+				s.push('e');
+				n += 1;
+
+				if sign == 1 {
+					s.push(self.char_at(n));
+					n += 1;
+				}
+
+				while DIGITS.contains(&self.at(n)) {
+					s.push(self.char_at(n));
+					n += 1;
+				}
+			}
+		}
+
 		self.advance(n);
-		Some(s.parse().unwrap())
+		Ok(Some(s.parse().unwrap()))
 	}
 
-	pub fn try_string(&mut self) -> Option<String> {
-		let mut n = 0;
+	/// Reads the unit directly after a number, if any.
+	pub fn try_unit(&mut self) -> ParseResult<Option<String>> {
+		let start = self.offset();
+
+		let unit = if self.try_char('%') {
+			"%".to_owned()
+		} else if let Some(unit) = self.try_symbol() {
+			unit
+		} else {
+			return Ok(None);
+		};
+
+		let lower = unit.to_ascii_lowercase();
+
+		if UNITS.contains(&lower.as_str()) {
+			return Ok(Some(unit));
+		}
+
+		let span = self.span_from(start);
+		let mut error = ParseError::new(ErrorKind::UnknownUnit, &format!("Unknown unit {}", unit), span);
+
+		// Suggest a unit that is only a typo or two away
+		let closest = UNITS
+			.iter()
+			.map(|u| (distance(&lower, u), u))
+			.filter(|(d, _)| *d <= 2 && *d < lower.len())
+			.min_by_key(|(d, _)| *d);
+
+		if let Some((_, u)) = closest {
+			error = error.with_suggestion(span, "a unit with a similar name exists", u);
+		}
+
+		Err(error)
+	}
+
+	/// Reads the text of a string up to its closing quote, which is consumed,
+	/// or up to a `$name` or `${` for the caller to handle, returning whether
+	/// the string ended. Like in CSS, a string has to be closed on the line it
+	/// was opened on, so `start` is where the string opened.
+	pub fn string_text(&mut self, start: usize, text: &mut String) -> ParseResult<bool> {
+		loop {
+			match self.at(0) {
+				b'"' => {
+					self.advance(1);
+					return Ok(true);
+				},
+				0 | b'\n' | b'\r' => {
+					let span = self.span_from(start);
+					return Err(ParseError::new(ErrorKind::UnclosedString, "\" not closed", span));
+				},
+				b'$' if self.at(1) == b'{' || self.at(1).is_ascii_alphabetic() => return Ok(false),
+				_ => if !self.try_escape(text) {
+					text.push(self.char_at(0));
+					self.advance(self.char_len(0));
+				},
+			}
+		}
+	}
+
+	/// Reads a string, keeping any interpolations in it as written.
+	pub fn try_string(&mut self) -> ParseResult<Option<String>> {
 		let mut s = "".to_owned();
 
-		if self.at(0) == b'"' {
+		if self.at(0) != b'"' {
+			return Ok(None);
+		}
+
+		let start = self.clone();
+		self.advance(1);
+
+		loop {
+			match self.string_text(start.offset(), &mut s) {
+				Ok(true) => break,
+				Ok(false) => (),
+				Err(e) => {
+					*self = start;
+					return Err(e);
+				},
+			}
+
+			if !self.peek(b"${") {
+				s.push('$');
+				self.advance(1);
+				continue;
+			}
+
+			// Interpolated expressions may hold strings of their own
+			let from = self.offset();
+			let mut depth = 0;
+			self.advance(1);
+
+			while ![b'\n', b'\r', 0].contains(&self.at(0)) {
+				match self.at(0) {
+					b'{' => depth += 1,
+					b'}' => depth -= 1,
+					b'"' => {
+						if let Err(e) = self.try_string() {
+							*self = start;
+							return Err(e);
+						}
+
+						continue;
+					},
+					_ => (),
+				}
+
+				self.advance(self.char_len(0));
+
+				if depth == 0 {
+					break;
+				}
+			}
+
+			s.push_str(&String::from_utf8_lossy(&self.input[from..self.position]));
+		}
+
+		Ok(Some(s))
+	}
+
+	/// Reads an escape sequence inside a string, pushing the character it
+	/// stands for onto `s`.
+	pub fn try_escape(&mut self, s: &mut String) -> bool {
+		if self.at(0) != b'\\' {
+			return false;
+		}
+
+		let mut n = 1;
+		let mut code = 0;
+
+		// CSS style escapes such as `\201C` take up to six hex digits
+		while n < 7 && HEX.contains(&self.at(n)) {
+			code = code * 16 + (self.at(n) as char).to_digit(16).unwrap();
 			n += 1;
-		} else {
-			return None;
 		}
 
-		while self.at(n) != b'"' {
-			if self.peek_at(n, b"\\\"") {
-				s.push('"');
+		if n > 1 {
+			s.push(char::from_u32(code).filter(|c| *c != '\0').unwrap_or(char::REPLACEMENT_CHARACTER));
+
+			// A single space after the digits only marks where they end
+			if self.peek_at(n, b"\r\n") {
 				n += 2;
-			} else if self.at(n) == 0 {
-				throw_error("\" not closed", self.position_at(n));
-				return None;
-			} else {
-				s.push(self.char_at(n));
+			} else if self.is_whitespace(&self.at(n)) {
 				n += 1;
 			}
+
+			self.advance(n);
+			return true;
 		}
 
-		self.advance(n + 1);
-		Some(s)
+		match self.at(1) {
+			b'n' => s.push('\n'),
+			b't' => s.push('\t'),
+			// An escaped line break continues the string on the next line
+			b'\n' => (),
+			b'\r' if self.at(2) == b'\n' => {
+				self.advance(1);
+			},
+			0 => {
+				self.advance(1);
+				return true;
+			},
+			_ => s.push(self.char_at(1)),
+		}
+
+		self.advance(1 + self.char_len(1));
+		true
 	}
 
 	pub fn try_symbol(&mut self) -> Option<String> {
@@ -286,67 +807,88 @@ impl<'a> Lexer<'a> {
 		Some(s)
 	}
 
-	pub fn try_hex(&mut self) -> Option<String> {
-		let mut n = 0;
-		let mut s = "".to_owned();
+	/// Tells a hex color such as `#fa0` apart from an id selector such as
+	/// `#nav`.
+	pub fn is_hex(&self) -> bool {
+		let n = self.hex_len();
+		self.at(0) == b'#' && n > 1 && !SYMBOL.contains(&self.at(n))
+	}
 
-		if self.at(0) == b'#' {
-			n += 1;
-		} else {
-			return None;
-		}
+	/// Returns the length of the `#` here and the hex digits after it.
+	fn hex_len(&self) -> usize {
+		let mut n = 1;
 
 		while HEX.contains(&self.at(n)) {
-			s.push(self.char_at(n));
 			n += 1;
 		}
 
-		if n == 1 {
-			throw_error("Expected hexadecimal", self.position_at(n));
-			return None;
+		n
+	}
+
+	pub fn try_hex(&mut self) -> ParseResult<Option<String>> {
+		if self.at(0) != b'#' {
+			return Ok(None);
+		}
+
+		let n = self.hex_len();
+
+		if !self.is_hex() {
+			return Err(ParseError::new(ErrorKind::ExpectedHex, "Expected hexadecimal", self.span_at(n, self.char_len(n))));
 		}
 
+		let s = String::from_utf8_lossy(&self.input[self.position + 1..self.position + n]).into_owned();
 		self.advance(n);
-		Some(s)
+		Ok(Some(s))
 	}
 
 	pub fn try_bool(&mut self) -> Option<bool> {
-		if self.peek(b"true") {
-			self.advance(4);
+		if self.try_keyword(b"true") {
 			Some(true)
-		} else if self.peek(b"false") {
-			self.advance(5);
+		} else if self.try_keyword(b"false") {
 			Some(false)
 		} else {
 			None
 		}
 	}
 
-	pub fn try_binary_op(&mut self) -> Option<String> {
+	/// Returns the longest operator starting `n` bytes ahead, out of the
+	/// built in ones and `custom`.
+	pub fn peek_operator<'o>(&self, n: usize, custom: impl IntoIterator<Item = &'o str>) -> Option<&'o str> {
+		OPERATORS
+			.iter()
+			.copied()
+			.chain(custom)
+			.filter(|op| self.peek_at(n, op.as_bytes()))
+			.max_by_key(|op| op.len())
+	}
+
+	/// Reads a whole run of operator characters, as in the declaration of a
+	/// custom operator.
+	pub fn try_operator_chars(&mut self) -> Option<String> {
 		let mut n = 0;
+		let mut s = "".to_owned();
 
-		while self.is_whitespace(&self.at(n)) {
+		while OPERATOR_CHARS.contains(&self.at(n)) {
+			s.push(self.char_at(n));
 			n += 1;
 		}
 
-		for op in self.binary_ops {
-			if self.peek_at(n, op) {
-				self.advance(n + op.len());
-				return Some(std::str::from_utf8(op).unwrap().into());
-			}
+		if n == 0 {
+			return None;
 		}
-			
-		None
+
+		self.advance(n);
+		Some(s)
 	}
 
 	pub fn try_arrow(&mut self) -> bool {
 		let mut n = 0;
 
-		while self.is_whitespace(&self.at(n)) {
+		while [b' ', b'\t'].contains(&self.at(n)) {
 			n += 1;
 		}
 
-		if self.peek_at(n, b"->") {
+		if self.peek_operator(n, None) == Some("->") {
 			self.advance(n + 2);
 			true
 		} else {
@@ -354,3 +896,71 @@ impl<'a> Lexer<'a> {
 		}
 	}
 }
+
+fn is_continuation(c: u8) -> bool {
+	c & 0xc0 == 0x80
+}
+
+/// Returns the edit distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+
+	for (i, ca) in a.chars().enumerate() {
+		let mut previous = row[0];
+		row[0] = i + 1;
+
+		for (j, cb) in b.iter().enumerate() {
+			let current = row[j + 1];
+			row[j + 1] = (previous + (ca != *cb) as usize).min(row[j] + 1).min(current + 1);
+			previous = current;
+		}
+	}
+
+	row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Reads a number and its unit from the start of `input`, along with how
+	/// much of it was read.
+	fn dimension(input: &str) -> ParseResult<(f32, Option<String>, usize)> {
+		let mut lexer = Lexer::new(input.as_bytes(), FileId::default());
+		let n = lexer.try_number()?.unwrap();
+		let unit = lexer.try_unit()?;
+
+		Ok((n, unit, lexer.offset()))
+	}
+
+	#[test]
+	fn numbers() {
+		assert_eq!(dimension(".5").unwrap(), (0.5, None, 2));
+		assert_eq!(dimension("1e-3").unwrap(), (0.001, None, 4));
+		assert_eq!(dimension("2.5E+2 ").unwrap(), (250.0, None, 6));
+		assert_eq!(dimension("10%").unwrap(), (10.0, Some("%".into()), 3));
+		assert_eq!(dimension("1em").unwrap(), (1.0, Some("em".into()), 3));
+		assert_eq!(dimension("1e3px").unwrap(), (1000.0, Some("px".into()), 5));
+		assert_eq!(dimension("3PX").unwrap(), (3.0, Some("PX".into()), 3));
+	}
+
+	#[test]
+	fn a_dot_needs_a_digit_after_it() {
+		assert!(Lexer::new(b".a", FileId::default()).try_number().unwrap().is_none());
+
+		let error = dimension("1.px").unwrap_err();
+		assert_eq!((error.kind, error.span.start, error.span.end), (ErrorKind::TrailingDot, 1, 2));
+	}
+
+	#[test]
+	fn unknown_units_suggest_a_close_one() {
+		let error = dimension("10pz").unwrap_err();
+		assert_eq!((error.kind, error.span.start, error.span.end), (ErrorKind::UnknownUnit, 2, 4));
+		assert_eq!(error.suggestions.iter().map(|s| s.2.as_str()).collect::<Vec<_>>(), vec!["px"]);
+
+		let error = dimension("10banana").unwrap_err();
+		assert_eq!(error.kind, ErrorKind::UnknownUnit);
+		assert!(error.suggestions.is_empty());
+	}
+}
@@ -32,514 +32,2075 @@
  */
 
 mod lexer;
+pub mod token;
 
-use crate::error::throw_error;
+use crate::error::{ErrorKind, ParseError, ParseResult};
 use crate::ast::*;
 use lexer::Lexer;
-use std::process::exit;
 use fnv::FnvHashMap;
 
-fn unexpected(lexer: &Lexer) {
-	throw_error(&format!("Unexpected symbol: {:?}", lexer.char_at(0)), lexer.position());
+/// Types that are built in and so cannot be declared again.
+static BUILTIN_TYPES: &[&str] = &["Number", "String", "Hex", "Dimension", "Bool"];
+
+/// Reports the first thing past any spaces as unexpected.
+fn unexpected(lexer: &Lexer) -> ParseError {
+	let n = lexer.spaces_len(0);
+
+	match lexer.at(n) {
+		0 => ParseError::new(ErrorKind::UnexpectedEnd, "Unexpected end of input", lexer.span_at(n, 0)),
+		b'\n' | b'\r' => ParseError::new(ErrorKind::UnexpectedEnd, "Unexpected end of line", lexer.span_at(n, 0)),
+		_ => ParseError::new(
+			ErrorKind::UnexpectedSymbol,
+			&format!("Unexpected symbol: {:?}", lexer.char_at(n)),
+			lexer.span_at(n, lexer.char_len(n)),
+		),
+	}
+}
+
+/// Reports a bracket opened at `open` that was not closed where expected. At
+/// the end of a line the bracket itself is pointed at, since the lines after
+/// it are not indented enough to continue it.
+fn unclosed(lexer: &Lexer, bracket: char, open: Span) -> ParseError {
+	if is_line_end(lexer) {
+		ParseError::new(ErrorKind::UnclosedBracket, &format!("{} not closed", bracket), open)
+	} else {
+		unexpected(lexer)
+	}
+}
+
+/// Fails if the line starting here is indented deeper than `depth`, pointing
+/// at its indentation.
+fn check_indent(lexer: &Lexer, depth: usize) -> ParseResult<()> {
+	let mut lexer = lexer.clone();
+	let start = lexer.offset();
+
+	match lexer.skip_indent()? {
+		Some(level) if level > depth => Err(ParseError::new(ErrorKind::UnexpectedIndent, "Unexpected indent", lexer.span_from(start))),
+		_ => Ok(()),
+	}
+}
+
+fn parse_function_type(lexer: &mut Lexer, first: Type, open: Span) -> ParseResult<TypeKind> {
+	let mut types = vec![first];
+
+	loop {
+		lexer.skip_continuation(0);
+
+		if let Some(t) = parse_type(lexer)? {
+			types.push(t);
+			lexer.skip_continuation(0);
+
+			if lexer.try_char(')') {
+				break;
+			} else if !lexer.try_arrow() {
+				return Err(unclosed(lexer, '(', open));
+			}
+		} else {
+			return Err(unclosed(lexer, '(', open));
+		}
+	}
+
+	Ok(TypeKind::Function(types))
 }
 
-// TODO: Function types such as (Number -> Number) -> Number
-pub fn parse_type(lexer: &mut Lexer) -> Option<Type> {
-	if lexer.try_peek(b"Number") {
-		Some(Type::Number)
-	} else if lexer.try_peek(b"String") {
-		Some(Type::String)
-	} else if lexer.try_peek(b"Hex") {
-		Some(Type::Hex)
-	} else if lexer.try_peek(b"Dimension") {
-		Some(Type::Dimension)
-	} else if lexer.try_peek(b"Bool") {
-		Some(Type::Bool)
+fn parse_type_kind(lexer: &mut Lexer) -> ParseResult<Option<TypeKind>> {
+	let open = lexer.span_at(0, 1);
+
+	if lexer.try_keyword(b"Number") {
+		Ok(Some(TypeKind::Number))
+	} else if lexer.try_keyword(b"String") {
+		Ok(Some(TypeKind::String))
+	} else if lexer.try_keyword(b"Hex") {
+		Ok(Some(TypeKind::Hex))
+	} else if lexer.try_keyword(b"Dimension") {
+		Ok(Some(TypeKind::Dimension))
+	} else if lexer.try_keyword(b"Bool") {
+		Ok(Some(TypeKind::Bool))
 	} else if lexer.try_char('(') {
-		lexer.skip_whitespace();
+		lexer.skip_continuation(0);
 
 		if lexer.try_char(')') {
-			Some(Type::Tuple(Vec::new()))
+			Ok(Some(TypeKind::Tuple(Vec::new())))
 		} else {
 			let mut types = Vec::new();
 
 			loop {
-				if let Some(t) = parse_type(lexer) {
+				if let Some(t) = parse_type(lexer)? {
+					lexer.skip_continuation(0);
+
+					// A parenthesized arrow such as (Number -> Number) is a function
+					if types.is_empty() && lexer.try_arrow() {
+						return Ok(Some(parse_function_type(lexer, t, open)?));
+					}
+
 					types.push(t);
-					lexer.skip_whitespace();
 
 					if lexer.try_char(')') {
 						break;
 					} else if !lexer.try_char(',') {
-						unexpected(lexer);
-						exit(0);
+						return Err(unclosed(lexer, '(', open));
 					}
 
-					lexer.skip_whitespace();
+					lexer.skip_continuation(0);
 				} else if lexer.try_char(')') {
 					break;
 				} else {
-					unexpected(lexer);
-					exit(0);
+					return Err(unclosed(lexer, '(', open));
 				}
 			}
 
-			Some(Type::Tuple(types))
+			Ok(Some(TypeKind::Tuple(types)))
 		}
 	} else if lexer.try_char('[') {
-		lexer.skip_whitespace();
+		lexer.skip_continuation(0);
 
-		if let Some(t) = parse_type(lexer) {
-			lexer.skip_whitespace();
+		if let Some(t) = parse_type(lexer)? {
+			lexer.skip_continuation(0);
 
 			if lexer.try_char(']') {
-				Some(Type::List(Box::new(t)))
+				Ok(Some(TypeKind::List(Box::new(t))))
 			} else {
-				unexpected(lexer);
-				exit(0);
+				Err(unclosed(lexer, '[', open))
 			}
 		} else {
-			unexpected(lexer);
-			exit(0);
+			Err(unclosed(lexer, '[', open))
 		}
 	} else if lexer.try_char('{') {
-		lexer.skip_whitespace();
+		lexer.skip_continuation(0);
 
 		if lexer.try_char('}') {
-			Some(Type::Record(FnvHashMap::default()))
+			Ok(Some(TypeKind::Record(FnvHashMap::default())))
 		} else {
 			let mut types = FnvHashMap::default();
 
 			loop {
 				if let Some(s) = lexer.try_symbol() {
-					lexer.skip_whitespace();
+					lexer.skip_continuation(0);
+
+					if !lexer.try_peek(b"::") {
+						return Err(unexpected(lexer));
+					}
 
-					if lexer.try_peek(b"::") {
-						lexer.skip_whitespace();
+					lexer.skip_continuation(0);
 
-						if let Some(t) = parse_type(lexer) {
-							types.insert(s, t);
-							lexer.skip_whitespace();
+					match parse_type(lexer)? {
+						Some(t) => types.insert(s, t),
+						None => return Err(unclosed(lexer, '{', open)),
+					};
 
-							if lexer.try_char('}') {
-								break;
-							} else if !lexer.try_char(',') {
-								unexpected(lexer);
-								exit(0);
-							}
+					lexer.skip_continuation(0);
 
-							lexer.skip_whitespace();
-						}
-					} else {
-						unexpected(lexer);
-						exit(0);
+					if lexer.try_char('}') {
+						break;
+					} else if !lexer.try_char(',') {
+						return Err(unclosed(lexer, '{', open));
 					}
+
+					lexer.skip_continuation(0);
 				} else if lexer.try_char('}') {
 					break;
 				} else {
-					unexpected(lexer);
-					exit(0);
+					return Err(unclosed(lexer, '{', open));
 				}
 			}
 
-			Some(Type::Record(types))
+			Ok(Some(TypeKind::Record(types)))
 		}
-	} else if let Some(s) = lexer.try_symbol() {
-		Some(Type::Alias(s))
 	} else {
-		None
+		Ok(lexer.try_symbol().map(TypeKind::Alias))
 	}
 }
 
-fn parse_tuple(lexer: &mut Lexer) -> Option<Expr> {
-	if lexer.try_char('(') {
-		lexer.skip_whitespace();
+/// Parses a type. Types are only written on unindented lines, so a type left
+/// open in brackets continues onto the lines indented below it.
+pub fn parse_type(lexer: &mut Lexer) -> ParseResult<Option<Type>> {
+	let start = lexer.offset();
+	let kind = parse_type_kind(lexer)?;
+	Ok(kind.map(|kind| Type { kind, span: lexer.span_from(start) }))
+}
 
-		if lexer.try_char(')') {
-			Some(Expr::Tuple(Vec::new()))
-		} else {
-			let mut contents = Vec::new();
-			let mut trailing = false;
+/// Returns the binary operators every file starts out with. Custom operators
+/// are added to these as they are declared.
+fn builtin_operators() -> FnvHashMap<String, Operator> {
+	let levels: &[&[&str]] = &[
+		&["|>"],
+		&["or"],
+		&["and"],
+		&["==", "!=", "<", ">", "<=", ">="],
+		&["++"],
+		&["+", "-"],
+		&["*", "/"],
+	];
+	let mut operators = FnvHashMap::default();
+
+	for (i, ops) in levels.iter().enumerate() {
+		for op in ops.iter() {
+			operators.insert(op.to_string(), Operator {
+				precedence: i as u8 + 1,
+				associativity: Associativity::Left,
+				function: None,
+			});
+		}
+	}
 
-			loop {
-				if let Some(e) = parse_expr(lexer) {
-					contents.push(e);
-					lexer.skip_whitespace();
+	operators
+}
 
-					if lexer.try_char(')') {
-						break;
-					} else if !lexer.try_char(',') {
-						unexpected(lexer);
-						exit(0);
-					}
+fn symbol_len(lexer: &Lexer, start: usize) -> usize {
+	let mut n = start;
 
-					lexer.skip_whitespace();
-				} else if lexer.try_char(')') {
-					trailing = true;
-					break;
-				} else {
-					unexpected(lexer);
-					exit(0);
-				}
-			}
+	if lexer.at(n).is_ascii_alphabetic() {
+		while lexer.at(n).is_ascii_alphanumeric() || [b'-', b'_'].contains(&lexer.at(n)) {
+			n += 1;
+		}
+	}
 
-			if contents.len() == 1 && !trailing {
-				Some(contents.get(0).unwrap().clone())
-			} else {
-				Some(Expr::Tuple(contents))
-			}
+	n - start
+}
+
+/// Returns the length of the property name here, which may have a vendor
+/// prefix as in `-webkit-transition` or be a custom property such as `--brand`.
+fn property_len(lexer: &Lexer) -> usize {
+	if lexer.peek(b"--") {
+		let mut n = 2;
+
+		while lexer.at(n).is_ascii_alphanumeric() || [b'-', b'_'].contains(&lexer.at(n)) {
+			n += 1;
+		}
+
+		if n > 2 { n } else { 0 }
+	} else if lexer.at(0) == b'-' {
+		match symbol_len(lexer, 1) {
+			0 => 0,
+			n => n + 1,
 		}
 	} else {
-		None
+		symbol_len(lexer, 0)
 	}
 }
 
-fn parse_list(lexer: &mut Lexer) -> Option<Expr> {
-	if lexer.try_char('[') {
-		lexer.skip_whitespace();
+/// Checks whether only spaces and comments are left on the line.
+fn is_line_end(lexer: &Lexer) -> bool {
+	[b'\n', b'\r', 0].contains(&lexer.at(lexer.spaces_len(0)))
+}
 
-		if lexer.try_char(']') {
-			Some(Expr::List(Vec::new()))
-		} else {
-			let mut contents = Vec::new();
+fn is_definition(lexer: &Lexer) -> bool {
+	if lexer.at(0) != b'$' {
+		return false;
+	}
 
-			loop {
-				if let Some(e) = parse_expr(lexer) {
-					contents.push(e);
-					lexer.skip_whitespace();
+	let mut n = 1 + symbol_len(lexer, 1);
 
-					if lexer.try_char(']') {
-						break;
-					} else if !lexer.try_char(',') {
-						unexpected(lexer);
-						exit(0);
-					}
+	while [b' ', b'\t'].contains(&lexer.at(n)) {
+		n += 1;
+	}
 
-					lexer.skip_whitespace();
-				} else if lexer.try_char(']') {
-					break;
-				} else {
-					unexpected(lexer);
-					exit(0);
-				}
-			}
+	n > 1 && lexer.at(n) == b'=' && lexer.at(n + 1) != b'='
+}
 
-			Some(Expr::List(contents))
-		}
-	} else {
-		None
+fn is_keyword(lexer: &Lexer) -> bool {
+	let n = symbol_len(lexer, 0);
+	lexer::KEYWORDS.iter().any(|k| k.len() == n && lexer.peek(k))
+}
+
+/// Checks whether a line at `depth` is a selector. Where properties may also
+/// appear, `a:hover` is only a selector when it has a block, and is otherwise
+/// a property such as `color:red`.
+fn is_selector(lexer: &Lexer, depth: usize, properties: bool) -> bool {
+	match lexer.at(0) {
+		b'.' | b'#' | b'&' | b'~' | b'*' | b'[' | b':' | b'>' | b'+' => true,
+		c if c.is_ascii_alphabetic() => {
+			let n = symbol_len(lexer, 0);
+
+			if is_keyword(lexer) || lexer.at(n) == b'(' {
+				false
+			} else if lexer.at(n) == b':' {
+				// `color: red` is always a property
+				!lexer.is_whitespace(&lexer.at(n + 1)) && lexer.at(n + 1) != 0 && (!properties || lexer.has_block(depth))
+			} else {
+				true
+			}
+		},
+		_ => false,
 	}
 }
 
-fn parse_record(lexer: &mut Lexer) -> Option<Expr> {
-	if lexer.try_char('{') {
-		lexer.skip_whitespace();
+struct Parser<'a> {
+	lexer: Lexer<'a>,
+	scopes: Vec<Vec<String>>,
+	enums: FnvHashMap<String, Vec<String>>,
+	aliases: FnvHashMap<String, Type>,
+	operators: FnvHashMap<String, Operator>,
+	depth: usize,
+	/// How many brackets the expression being parsed is inside.
+	brackets: usize,
+	errors: Vec<ParseError>,
+}
 
-		if lexer.try_char('}') {
-			Some(Expr::Record(FnvHashMap::default()))
+impl<'a> Parser<'a> {
+	/// Skips spaces, and inside brackets any line breaks before lines that
+	/// continue the expression.
+	fn skip_space(&mut self) {
+		if self.brackets > 0 {
+			self.lexer.skip_continuation(self.depth);
 		} else {
-			let mut contents = FnvHashMap::default();
+			self.lexer.skip_spaces();
+		}
+	}
 
-			loop {
-				if let Some(s) = lexer.try_symbol() {
-					lexer.skip_whitespace();
+	/// Parses an expression inside brackets, which may go on over several
+	/// lines.
+	fn parse_bracketed(&mut self) -> ParseResult<Option<Expr>> {
+		self.brackets += 1;
+		let e = self.parse_expr();
+		self.brackets -= 1;
+		e
+	}
 
-					if lexer.try_char(':') {
-						lexer.skip_whitespace();
+	fn parse_tuple(&mut self) -> ParseResult<Option<ExprKind>> {
+		let open = self.lexer.span_at(0, 1);
 
-						if let Some(e) = parse_expr(lexer) {
-							contents.insert(s, e);
-							lexer.skip_whitespace();
+		if self.lexer.try_char('(') {
+			self.lexer.skip_continuation(self.depth);
 
-							if lexer.try_char('}') {
-								break;
-							} else if !lexer.try_char(',') {
-								unexpected(lexer);
-								exit(0);
-							}
+			if self.lexer.try_char(')') {
+				Ok(Some(ExprKind::Tuple(Vec::new())))
+			} else {
+				let mut contents = Vec::new();
+				let mut trailing = false;
+
+				loop {
+					if let Some(e) = self.parse_bracketed()? {
+						contents.push(e);
+						self.lexer.skip_continuation(self.depth);
 
-							lexer.skip_whitespace();
+						if self.lexer.try_char(')') {
+							break;
+						} else if !self.lexer.try_char(',') {
+							return Err(unclosed(&self.lexer, '(', open));
 						}
+
+						self.lexer.skip_continuation(self.depth);
+					} else if self.lexer.try_char(')') {
+						trailing = true;
+						break;
 					} else {
-						unexpected(lexer);
-						exit(0);
+						return Err(unclosed(&self.lexer, '(', open));
 					}
-				} else if lexer.try_char('}') {
-					break;
+				}
+
+				if contents.len() == 1 && !trailing {
+					Ok(Some(contents.remove(0).kind))
 				} else {
-					unexpected(lexer);
-					exit(0);
+					Ok(Some(ExprKind::Tuple(contents)))
 				}
 			}
-
-			Some(Expr::Record(contents))
-		}
-	} else {
-		None
-	}
-}
-
-fn parse_name(lexer: &mut Lexer) -> Option<Expr> {
-	if lexer.try_char('$') {
-		if let Some(s) = lexer.try_symbol() {
-			Some(Expr::Symbol(s))
 		} else {
-			unexpected(lexer);
-			exit(0);
+			Ok(None)
 		}
-	} else {
-		None
 	}
-}
 
-fn parse_call_or_symbol(lexer: &mut Lexer) -> Option<Expr> {
-	if let Some(s) = lexer.try_symbol() {
-		if lexer.try_char('(') {
-			lexer.skip_whitespace();
+	fn parse_list(&mut self) -> ParseResult<Option<ExprKind>> {
+		let open = self.lexer.span_at(0, 1);
 
-			if lexer.try_char(')') {
-				Some(Expr::Call(s, Vec::new()))
+		if self.lexer.try_char('[') {
+			self.lexer.skip_continuation(self.depth);
+
+			if self.lexer.try_char(']') {
+				Ok(Some(ExprKind::List(Vec::new())))
 			} else {
 				let mut contents = Vec::new();
 
 				loop {
-					if let Some(e) = parse_expr(lexer) {
+					if let Some(e) = self.parse_bracketed()? {
 						contents.push(e);
-						lexer.skip_whitespace();
+						self.lexer.skip_continuation(self.depth);
 
-						if lexer.try_char(')') {
+						if self.lexer.try_char(']') {
 							break;
-						} else if !lexer.try_char(',') {
-							unexpected(lexer);
-							exit(0);
+						} else if !self.lexer.try_char(',') {
+							return Err(unclosed(&self.lexer, '[', open));
 						}
 
-						lexer.skip_whitespace();
-					} else if lexer.try_char(')') {
+						self.lexer.skip_continuation(self.depth);
+					} else if self.lexer.try_char(']') {
 						break;
 					} else {
-						unexpected(lexer);
-						exit(0);
+						return Err(unclosed(&self.lexer, '[', open));
 					}
 				}
 
-				Some(Expr::Call(s, contents))
+				Ok(Some(ExprKind::List(contents)))
 			}
 		} else {
-			Some(Expr::Symbol(s))
+			Ok(None)
 		}
-	} else {
-		None
 	}
-}
 
-fn parse_expr(lexer: &mut Lexer) -> Option<Expr> {
-	let a =
-		if let Some(e) = parse_tuple(lexer) {
-			e
-		} else if let Some(e) = parse_list(lexer) {
-			e
-		} else if let Some(e) = parse_record(lexer) {
-			e
-		} else if let Some(e) = parse_name(lexer) {
-			e
-		} else if let Some(n) = lexer.try_number() {
-			if let Some(u) = lexer.try_symbol() {
-				Expr::Dimension(n, u)
-			} else {
-				Expr::Number(n)
-			}
-		} else if let Some(s) = lexer.try_string() {
-			Expr::String(s)
-		} else if let Some(h) = lexer.try_hex() {
-			Expr::Hex(h)
-		} else if let Some(b) = lexer.try_bool() {
-			Expr::Bool(b)
-		} else if let Some(e) = parse_call_or_symbol(lexer) {
-			e
-		} else {
-			return None;
-		};
+	/// Parses a record, which may be spread over several lines with the
+	/// commas and braces lined up in front of the fields.
+	fn parse_record(&mut self) -> ParseResult<Option<ExprKind>> {
+		let open = self.lexer.span_at(0, 1);
 
-	Some(
-		if lexer.try_char('[') {
-			lexer.skip_whitespace();
+		if !self.lexer.try_char('{') {
+			return Ok(None);
+		}
 
-			if let Some(e) = parse_expr(lexer) {
-				lexer.skip_whitespace();
+		let mut contents = FnvHashMap::default();
+		let mut spans: FnvHashMap<String, Span> = FnvHashMap::default();
 
-				if lexer.try_char(']') {
-					Expr::Index(Box::new(a), Box::new(e))
-				} else {
-					unexpected(lexer);
-					exit(0);
+		loop {
+			self.lexer.skip_continuation(self.depth);
+			let start = self.lexer.offset();
+
+			if let Some(s) = self.lexer.try_symbol() {
+				let span = self.lexer.span_from(start);
+
+				if let Some(first) = spans.get(&s) {
+					return Err(
+						ParseError::new(ErrorKind::AlreadyDefined, &format!("Field {} is set twice", s), span)
+							.with_label(*first, "first set here")
+					);
+				}
+
+				self.lexer.skip_continuation(self.depth);
+
+				if !self.lexer.try_char(':') {
+					return Err(unexpected(&self.lexer));
+				}
+
+				self.lexer.skip_continuation(self.depth);
+
+				match self.parse_bracketed()? {
+					Some(e) => contents.insert(s.clone(), e),
+					None => return Err(unclosed(&self.lexer, '{', open)),
+				};
+
+				spans.insert(s, span);
+				self.lexer.skip_continuation(self.depth);
+
+				if self.lexer.try_char('}') {
+					break;
+				} else if !self.lexer.try_char(',') {
+					return Err(unclosed(&self.lexer, '{', open));
 				}
+			} else if self.lexer.try_char('}') {
+				break;
 			} else {
-				unexpected(lexer);
-				exit(0);
+				return Err(unclosed(&self.lexer, '{', open));
 			}
-		} else if let Some(op) = lexer.try_binary_op() {
-			lexer.skip_whitespace();
+		}
+
+		Ok(Some(ExprKind::Record(contents)))
+	}
 
-			if let Some(b) = parse_expr(lexer) {
-				Expr::BinaryOp(op, Box::new(a), Box::new(b))
+	fn define(&mut self, name: &str, span: Span) -> ParseResult<()> {
+		let scope = self.scopes.last_mut().unwrap();
+
+		if scope.iter().any(|s| s == name) {
+			return Err(ParseError::new(ErrorKind::AlreadyDefined, &format!("${} is already defined", name), span));
+		}
+
+		scope.push(name.to_owned());
+		Ok(())
+	}
+
+	fn is_type(&self, name: &str) -> bool {
+		BUILTIN_TYPES.contains(&name) || self.enums.contains_key(name) || self.aliases.contains_key(name)
+	}
+
+	/// Replaces the aliases and enums named in a type with what they stand for.
+	/// On error, `visiting` is left holding the aliases that led to it.
+	fn resolve_type(&self, t: &Type, visiting: &mut Vec<String>) -> ParseResult<Type> {
+		let kind = match &t.kind {
+			TypeKind::Tuple(types) => TypeKind::Tuple(
+				types.iter().map(|t| self.resolve_type(t, visiting)).collect::<ParseResult<_>>()?
+			),
+			TypeKind::List(t) => TypeKind::List(Box::new(self.resolve_type(t, visiting)?)),
+			TypeKind::Record(types) => TypeKind::Record(
+				types.iter().map(|(s, t)| Ok((s.clone(), self.resolve_type(t, visiting)?))).collect::<ParseResult<_>>()?
+			),
+			TypeKind::Function(types) => TypeKind::Function(
+				types.iter().map(|t| self.resolve_type(t, visiting)).collect::<ParseResult<_>>()?
+			),
+			TypeKind::Alias(s) if self.enums.contains_key(s) => TypeKind::Enum(s.clone()),
+			TypeKind::Alias(s) if visiting.contains(s) => {
+				return Err(ParseError::new(ErrorKind::CyclicType, &format!("Type {} is defined in terms of itself", s), t.span));
+			},
+			TypeKind::Alias(s) if self.aliases.contains_key(s) => {
+				visiting.push(s.clone());
+				let resolved = self.resolve_type(&self.aliases[s], visiting)?;
+				visiting.pop();
+				resolved.kind
+			},
+			kind => kind.clone(),
+		};
+
+		// The expansion of an alias points at where the alias was used
+		Ok(Type { kind, span: t.span })
+	}
+
+	fn parse_name(&mut self) -> ParseResult<Option<ExprKind>> {
+		let start = self.lexer.offset();
+
+		if self.lexer.try_char('$') {
+			if let Some(s) = self.lexer.try_symbol() {
+				// Names resolve lexically, so the innermost definition wins
+				if self.scopes.iter().rev().any(|scope| scope.contains(&s)) {
+					Ok(Some(ExprKind::Variable(s)))
+				} else {
+					let span = self.lexer.span_from(start);
+					Err(ParseError::new(ErrorKind::UndefinedVariable, &format!("${} is not defined", s), span))
+				}
 			} else {
-				unexpected(lexer);
-				exit(0);
+				Err(unexpected(&self.lexer))
 			}
 		} else {
-			a
+			Ok(None)
 		}
-	)
-}
+	}
 
-// fn parse_selector(lexer: &mut Lexer) -> Option<Node> {
+	/// Parses a string, splicing in `$name` and `${expr}`.
+	fn parse_string(&mut self) -> ParseResult<Option<ExprKind>> {
+		let start = self.lexer.offset();
 
-// }
+		if !self.lexer.try_char('"') {
+			return Ok(None);
+		}
+
+		let mut parts = Vec::new();
+		let mut text = "".to_owned();
+
+		while !self.lexer.string_text(start, &mut text)? {
+			if !text.is_empty() {
+				parts.push(StringPart::Text(text));
+				text = "".to_owned();
+			}
+
+			let start = self.lexer.offset();
+
+			let e = if self.lexer.peek(b"${") {
+				self.lexer.advance(2);
+				self.lexer.skip_spaces();
+
+				let e = match self.parse_expr()? {
+					Some(e) => e,
+					None => return Err(unexpected(&self.lexer)),
+				};
+
+				self.lexer.skip_spaces();
 
-fn parse_atcss(lexer: &mut Lexer) -> Option<Node> {
-	if lexer.try_peek(b"@css") {
-		lexer.skip_whitespace();
-		if let Some(r) = parse_expr(lexer) {
-			return Some(Node::AtCSS(r));
+				if !self.lexer.try_char('}') {
+					return Err(unexpected(&self.lexer));
+				}
+
+				e
+			} else {
+				let kind = self.parse_name()?.unwrap();
+				Expr { kind, span: self.lexer.span_from(start) }
+			};
+
+			parts.push(StringPart::Expr(e));
+		}
+
+		if parts.is_empty() {
+			Ok(Some(ExprKind::String(text)))
 		} else {
-			unexpected(lexer);
-			exit(0);
+			if !text.is_empty() {
+				parts.push(StringPart::Text(text));
+			}
+
+			Ok(Some(ExprKind::Interpolated(parts)))
 		}
 	}
-	None
-}
 
-fn parse_function(lexer: &mut Lexer) -> Option<Node> {
-	if let Some(s) = lexer.try_symbol() {
-		if lexer.try_char('(') {
-			lexer.skip_whitespace();
+	fn parse_call_or_symbol(&mut self) -> ParseResult<Option<ExprKind>> {
+		if is_keyword(&self.lexer) {
+			return Ok(None);
+		}
 
-			let params = if lexer.try_char(')') {
-				Vec::new()
-			} else {
-				let mut params = Vec::new();
+		// Custom properties such as `--brand` are names rather than negations
+		if self.lexer.peek(b"--") && property_len(&self.lexer) > 0 {
+			let n = property_len(&self.lexer);
+			let name = (0..n).map(|i| self.lexer.at(i) as char).collect();
+			self.lexer.advance(n);
+			return Ok(Some(ExprKind::Symbol(name)));
+		}
 
-				loop {
-					if let Some(s) = lexer.try_symbol() {
-						params.push(s);
-						lexer.skip_whitespace();
+		if let Some(s) = self.lexer.try_symbol() {
+			let open = self.lexer.span_at(0, 1);
+
+			if self.lexer.try_char('(') {
+				self.lexer.skip_continuation(self.depth);
+
+				if self.lexer.try_char(')') {
+					Ok(Some(ExprKind::Call(s, Vec::new())))
+				} else {
+					let mut contents = Vec::new();
+
+					loop {
+						if let Some(e) = self.parse_bracketed()? {
+							contents.push(e);
+							self.lexer.skip_continuation(self.depth);
+
+							if self.lexer.try_char(')') {
+								break;
+							} else if !self.lexer.try_char(',') {
+								return Err(unclosed(&self.lexer, '(', open));
+							}
 
-						if lexer.try_char(')') {
+							self.lexer.skip_continuation(self.depth);
+						} else if self.lexer.try_char(')') {
 							break;
-						} else if !lexer.try_char(',') {
-							unexpected(lexer);
-							exit(0);
+						} else {
+							return Err(unclosed(&self.lexer, '(', open));
 						}
+					}
+
+					Ok(Some(ExprKind::Call(s, contents)))
+				}
+			} else {
+				Ok(Some(ExprKind::Symbol(s)))
+			}
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn parse_lambda_params(&mut self) -> Option<Vec<String>> {
+		if is_keyword(&self.lexer) {
+			None
+		} else if let Some(s) = self.lexer.try_symbol() {
+			Some(vec![s])
+		} else if self.lexer.try_char('(') {
+			let mut params = Vec::new();
+
+			loop {
+				self.lexer.skip_continuation(self.depth);
+
+				if self.lexer.try_char(')') {
+					break;
+				} else if let Some(s) = self.lexer.try_symbol() {
+					params.push(s);
+					self.lexer.skip_continuation(self.depth);
 
-						lexer.skip_whitespace();
-					} else if lexer.try_char(')') {
+					if self.lexer.try_char(')') {
 						break;
-					} else {
-						unexpected(lexer);
-						exit(0);
+					} else if !self.lexer.try_char(',') {
+						return None;
 					}
+				} else {
+					return None;
 				}
+			}
 
-				params
-			};
+			Some(params)
+		} else {
+			None
+		}
+	}
 
-			lexer.skip_whitespace();
+	fn parse_lambda(&mut self) -> ParseResult<Option<ExprKind>> {
+		let start = self.lexer.clone();
+
+		// Both `n` and `(a, b)` also start other expressions, so only commit
+		// once the arrow is found
+		let params = match self.parse_lambda_params() {
+			Some(params) if self.lexer.try_arrow() => params,
+			_ => {
+				self.lexer = start;
+				return Ok(None);
+			},
+		};
 
-			let types = if lexer.try_peek(b"::") {
-				lexer.skip_whitespace();
+		self.skip_space();
+		self.scopes.push(params.clone());
+		let body = self.parse_expr();
+		self.scopes.pop();
 
-				if let Some(t) = parse_type(lexer) {
-					let mut types = vec![t];
+		if let Some(e) = body? {
+			Ok(Some(ExprKind::Lambda(params, Box::new(e))))
+		} else {
+			Err(unexpected(&self.lexer))
+		}
+	}
 
-					while lexer.try_arrow() {
-						lexer.skip_whitespace();
+	fn parse_primary(&mut self) -> ParseResult<Option<Expr>> {
+		let start = self.lexer.offset();
+		let kind =
+			if let Some(e) = self.parse_lambda()? {
+				e
+			} else if let Some(e) = self.parse_tuple()? {
+				e
+			} else if let Some(e) = self.parse_list()? {
+				e
+			} else if let Some(e) = self.parse_record()? {
+				e
+			} else if let Some(e) = self.parse_name()? {
+				e
+			} else if self.lexer.try_char('&') {
+				ExprKind::Element("&".into())
+			} else if self.lexer.try_peek(b"~/") {
+				ExprKind::Element("~/".into())
+			} else if let Some(e) = self.parse_if_expr()? {
+				e
+			} else if let Some(n) = self.lexer.try_number()? {
+				if let Some(u) = self.lexer.try_unit()? {
+					ExprKind::Dimension(n, u)
+				} else {
+					ExprKind::Number(n)
+				}
+			} else if let Some(e) = self.parse_string()? {
+				e
+			} else if let Some(h) = self.lexer.try_hex()? {
+				ExprKind::Hex(h)
+			} else if let Some(b) = self.lexer.try_bool() {
+				ExprKind::Bool(b)
+			} else {
+				match self.parse_call_or_symbol()? {
+					Some(e) => e,
+					None => return Ok(None),
+				}
+			};
 
-						if let Some(t) = parse_type(lexer) {
-							types.push(t);
-						} else {
-							unexpected(lexer);
-							exit(0);
-						}
-					}
+		let a = Expr { kind, span: self.lexer.span_from(start) };
+		self.parse_postfix(a)
+	}
 
-					if lexer.try_newline() {
-						types
+	fn parse_postfix(&mut self, mut a: Expr) -> ParseResult<Option<Expr>> {
+		loop {
+			let open = self.lexer.span_at(0, 1);
+
+			if self.lexer.try_char('[') {
+				self.lexer.skip_continuation(self.depth);
+
+				if let Some(e) = self.parse_bracketed()? {
+					self.lexer.skip_continuation(self.depth);
+
+					if self.lexer.try_char(']') {
+						let span = self.lexer.span_from(a.span.start);
+						a = Expr { kind: ExprKind::Index(Box::new(a), Box::new(e)), span };
 					} else {
-						unexpected(lexer);
-						exit(0);
+						return Err(unclosed(&self.lexer, '[', open));
 					}
 				} else {
-					unexpected(lexer);
-					exit(0);
+					return Err(unclosed(&self.lexer, '[', open));
 				}
+			} else if self.lexer.at(0) == b'.' && self.lexer.at(1).is_ascii_alphabetic() {
+				self.lexer.advance(1);
+				let start = self.lexer.offset();
+				let field = self.lexer.try_symbol().unwrap();
+				let field = Expr { kind: ExprKind::Symbol(field), span: self.lexer.span_from(start) };
+				let span = self.lexer.span_from(a.span.start);
+				a = Expr { kind: ExprKind::BinaryOp(".".into(), Box::new(a), Box::new(field)), span };
+			} else {
+				return Ok(Some(a));
+			}
+		}
+	}
+
+	fn parse_unary(&mut self) -> ParseResult<Option<Expr>> {
+		let start = self.lexer.offset();
+		let op =
+			if self.lexer.try_keyword(b"not") {
+				self.lexer.skip_spaces();
+				"not"
+			} else if [b'-', b'+'].contains(&self.lexer.at(0)) && !self.lexer.is_whitespace(&self.lexer.at(1)) && !self.lexer.peek(b"--") {
+				let op = if self.lexer.at(0) == b'-' { "-" } else { "+" };
+				self.lexer.advance(1);
+				op
 			} else {
-				unexpected(lexer);
-				exit(0);
+				return self.parse_primary();
 			};
 
-			let mut nodes = Vec::new();
+		if let Some(e) = self.parse_unary()? {
+			let span = self.lexer.span_from(start);
 
-			loop {
-				while lexer.try_newline() {}
-
-				if lexer.try_indent(1) {
-					if let Some(e) = parse_expr(lexer) {
-						nodes.push(Node::Expr(e));
-					} else if let Some(e) = parse_atcss(lexer) {
-						nodes.push(e);
-					} 
-				} else {
-					break;
-				}
-			}
+			// A minus written right before a number is part of it
+			let kind = match e.kind {
+				ExprKind::Number(n) if op == "-" => ExprKind::Number(-n),
+				ExprKind::Dimension(n, u) if op == "-" => ExprKind::Dimension(-n, u),
+				kind => ExprKind::UnaryOp(op.into(), Box::new(Expr { kind, span: e.span })),
+			};
 
-			Some(Node::Function(s, params, types, nodes))
+			Ok(Some(Expr { kind, span }))
 		} else {
-			unexpected(lexer);
-			exit(0);
+			Err(unexpected(&self.lexer))
 		}
-	} else {
-		None
 	}
-}
 
-// fn parse_definition(lexer: &mut Lexer) -> Option<Node> {
+	/// Returns the next binary operator along with the number of bytes to
+	/// advance past it, without consuming anything.
+	fn peek_binary_op(&self) -> Option<(String, usize)> {
+		let n = self.lexer.spaces_len(0);
 
-// }
+		let len = symbol_len(&self.lexer, n);
 
-// fn parse_enum(lexer: &mut Lexer) -> Option<Node> {
+		let op = if len > 0 {
+			// Word operators need a boundary on both sides
+			if n == 0 {
+				return None;
+			}
 
-// }
+			self.operators.keys().find(|op| op.len() == len && self.lexer.peek_at(n, op.as_bytes()))?
+		} else {
+			let op = self.lexer.peek_operator(n, self.operators.keys().map(String::as_str))?;
 
-fn parse_root_node(lexer: &mut Lexer) -> Option<Node> {
-	// if let Some(n) = parse_selector(lexer) {
-	// 	n
-	// } else 
-	if let Some(n) = parse_function(lexer) {
-		Some(n)
-	// } else if let Some(n) = parse_definition(lexer) {
-	// 	n
-	// } else if let Some(n) = parse_enum(lexer) {
-	// 	n
-	} else if lexer.try_newline() {
-		None
-	} else {
-		unexpected(lexer);
-		exit(0);
+			// `0 -1px` is two values rather than a subtraction
+			if (op == "-" || op == "+") && n > 0 && !self.lexer.is_whitespace(&self.lexer.at(n + 1)) {
+				return None;
+			}
+
+			op
+		};
+
+		self.operators.get_key_value(op).map(|(op, _)| (op.clone(), n + op.len()))
 	}
-}
 
-pub fn parse(input: &[u8]) -> Vec<Node> {
-	let mut lexer = Lexer::new(&input);
-	let mut ast = Vec::new();
+	fn parse_binary(&mut self, min: u8) -> ParseResult<Option<Expr>> {
+		let mut a = match self.parse_unary()? {
+			Some(e) => e,
+			None => return Ok(None),
+		};
+		let mut last: Option<(String, Operator)> = None;
+
+		while let Some((op, n)) = self.peek_binary_op() {
+			let operator = self.operators[&op].clone();
+
+			if operator.precedence < min {
+				break;
+			}
+
+			// Operators of the same precedence only group on their own if they
+			// both lean the same way
+			let clashes = |o: &Operator| {
+				o.precedence == operator.precedence
+					&& (o.associativity != operator.associativity || o.associativity == Associativity::None)
+			};
+
+			if let Some((last, _)) = last.as_ref().filter(|(_, o)| clashes(o)) {
+				let span = self.lexer.span_at(n - op.len(), op.len());
+				let message = format!("{} cannot be chained with {} without parentheses", last, op);
+				return Err(ParseError::new(ErrorKind::NonAssociative, &message, span));
+			}
+
+			self.lexer.skip_spaces();
+			self.lexer.advance(op.len());
+			self.skip_space();
+
+			// Left associative operators only take tighter operators on their
+			// right, while right associative ones also take themselves
+			let next = match operator.associativity {
+				Associativity::Right => operator.precedence,
+				_ => operator.precedence + 1,
+			};
 
-	while lexer.has_left() {
-		match parse_root_node(&mut lexer) {
-			Some(n) => ast.push(n),
-			None => (),
+			if let Some(b) = self.parse_binary(next)? {
+				let span = a.span.to(b.span);
+
+				let kind = match &operator.function {
+					Some(f) => ExprKind::Call(f.clone(), vec![a, b]),
+					None => ExprKind::BinaryOp(op.clone(), Box::new(a), Box::new(b)),
+				};
+
+				a = Expr { kind, span };
+				last = Some((op, operator));
+			} else {
+				return Err(unexpected(&self.lexer));
+			}
 		}
+
+		Ok(Some(a))
+	}
+
+	fn parse_expr(&mut self) -> ParseResult<Option<Expr>> {
+		self.parse_binary(0)
 	}
 
-	ast
+	fn parse_condition(&mut self) -> ParseResult<Expr> {
+		self.lexer.skip_spaces();
+
+		if let Some(e) = self.parse_expr()? {
+			if is_line_end(&self.lexer) {
+				return Ok(e);
+			}
+		}
+
+		Err(unexpected(&self.lexer))
+	}
+
+	fn try_else(&mut self, depth: usize) -> ParseResult<bool> {
+		let start = self.lexer.clone();
+
+		while self.lexer.try_newline() {}
+
+		if self.lexer.try_indent(depth)? && { self.lexer.skip_spaces(); self.lexer.try_keyword(b"else") } {
+			self.lexer.skip_spaces();
+			Ok(true)
+		} else {
+			self.lexer = start;
+			Ok(false)
+		}
+	}
+
+	/// Parses the indented lines of an if expression, the last of which is
+	/// its value.
+	fn parse_expr_block(&mut self, depth: usize) -> ParseResult<Vec<Expr>> {
+		let mut exprs = Vec::new();
+
+		loop {
+			let start = self.lexer.clone();
+
+			while self.lexer.try_newline() {}
+
+			if self.lexer.try_indent(depth)? {
+				self.depth = depth;
+				self.lexer.skip_spaces();
+
+				match self.parse_expr()? {
+					Some(e) if is_line_end(&self.lexer) => exprs.push(e),
+					_ => return Err(unexpected(&self.lexer)),
+				}
+			} else {
+				self.lexer = start;
+				break;
+			}
+		}
+
+		if exprs.is_empty() {
+			return Err(unexpected(&self.lexer));
+		}
+
+		self.depth = depth - 1;
+		Ok(exprs)
+	}
+
+	fn parse_if_expr(&mut self) -> ParseResult<Option<ExprKind>> {
+		let span = self.lexer.span_at(0, 2);
+
+		if !self.lexer.try_keyword(b"if") {
+			return Ok(None);
+		}
+
+		let depth = self.depth;
+		let condition = self.parse_condition()?;
+		let then = self.parse_expr_block(depth + 1)?;
+
+		if !self.try_else(depth)? {
+			return Err(ParseError::new(ErrorKind::MissingElse, "if expression is missing an else", span));
+		}
+
+		let start = self.lexer.offset();
+
+		let otherwise = if let Some(kind) = self.parse_if_expr()? {
+			vec![Expr { kind, span: self.lexer.span_from(start) }]
+		} else if is_line_end(&self.lexer) {
+			self.parse_expr_block(depth + 1)?
+		} else {
+			return Err(unexpected(&self.lexer));
+		};
+
+		Ok(Some(ExprKind::If(Box::new(condition), then, otherwise)))
+	}
+
+	fn parse_if(&mut self, depth: usize, selectors: bool) -> ParseResult<Option<NodeKind>> {
+		let span = self.lexer.span_at(0, 2);
+
+		if !self.lexer.try_keyword(b"if") {
+			return Ok(None);
+		}
+
+		let condition = self.parse_condition()?;
+		let then = self.parse_body(depth + 1, selectors);
+
+		let otherwise = if self.try_else(depth)? {
+			let start = self.lexer.offset();
+
+			if let Some(kind) = self.parse_if(depth, selectors)? {
+				vec![Node { kind, span: self.lexer.span_from(start), comments: Vec::new() }]
+			} else if is_line_end(&self.lexer) {
+				self.parse_body(depth + 1, selectors)
+			} else {
+				return Err(unexpected(&self.lexer));
+			}
+		} else if let Some(Node { kind: NodeKind::Expr(_), .. }) = then.last() {
+			// Leaving out the else is only allowed when returning props
+			return Err(ParseError::new(ErrorKind::MissingElse, "if returning a value is missing an else", span));
+		} else {
+			Vec::new()
+		};
+
+		Ok(Some(NodeKind::If(condition, then, otherwise)))
+	}
+
+	/// Records an error and skips to the next line indented at most `depth`
+	/// levels, so that parsing can carry on from a sibling of the bad line.
+	fn recover(&mut self, error: ParseError, depth: usize) {
+		self.errors.push(error);
+		self.lexer.skip_line();
+
+		while self.lexer.has_left() {
+			match self.lexer.peek_indent() {
+				Ok(Some(n)) if n <= depth => break,
+				// Comments may run over several lines
+				Ok(None) if self.lexer.try_newline() => (),
+				_ => self.lexer.skip_line(),
+			}
+		}
+	}
+
+	fn parse_body_node(&mut self, depth: usize, selectors: bool) -> ParseResult<Node> {
+		let start = self.lexer.offset();
+
+		let kind =
+			if let Some(n) = self.parse_definition()? {
+				n
+			} else if let Some(n) = self.parse_if(depth, selectors)? {
+				n
+			} else if selectors && is_selector(&self.lexer, depth, true) {
+				self.parse_selector(depth)?.unwrap()
+			} else if let Some(n) = self.parse_property()? {
+				n
+			} else if let Some(n) = self.parse_atcss()? {
+				n
+			} else if let Some(n) = self.parse_atdata(depth, selectors)? {
+				n
+			} else if let Some(n) = self.parse_event(depth, selectors)? {
+				n
+			} else if let Some(e) = self.parse_expr()? {
+				NodeKind::Expr(e)
+			} else {
+				return Err(unexpected(&self.lexer));
+			};
+
+		// Whatever is left on the line would otherwise be read as the next node
+		if !is_line_end(&self.lexer) {
+			return Err(unexpected(&self.lexer));
+		}
+
+		Ok(Node { kind, span: self.lexer.span_from(start), comments: Vec::new() })
+	}
+
+	/// Parses the indented lines of a selector or function. Nested selectors
+	/// are only allowed in the former.
+	fn parse_body(&mut self, depth: usize, selectors: bool) -> Vec<Node> {
+		let mut nodes = Vec::new();
+		self.scopes.push(Vec::new());
+
+		loop {
+			let start = self.lexer.clone();
+
+			while self.lexer.try_newline() {}
+
+			match self.lexer.try_indent(depth) {
+				Ok(true) => {
+					self.depth = depth;
+					// Comments may come before the node on its line
+					self.lexer.skip_spaces();
+
+					match self.parse_body_node(depth, selectors) {
+						Ok(n) => nodes.push(n),
+						Err(e) => self.recover(e, depth),
+					}
+				},
+				Ok(false) => match check_indent(&self.lexer, depth) {
+					Ok(()) => {
+						// Leave the line break for whoever parses the next line
+						self.lexer = start;
+						break;
+					},
+					Err(e) => self.recover(e, depth),
+				},
+				Err(e) => self.recover(e, depth),
+			}
+		}
+
+		self.scopes.pop();
+		nodes
+	}
+
+	fn parse_selector(&mut self, depth: usize) -> ParseResult<Option<NodeKind>> {
+		if !is_selector(&self.lexer, depth, depth > 0) {
+			return Ok(None);
+		}
+
+		let start = self.lexer.offset();
+		let mut selectors = Vec::new();
+		let mut s = "".to_owned();
+		// Where the attribute value being read started, if any
+		let mut quote = None;
+		// How many brackets are open, as in `:not(.a, .b)`
+		let mut nested = 0usize;
+
+		while quote.is_some() || !is_line_end(&self.lexer) {
+			let c = self.lexer.at(0);
+			let quoted = quote.is_some();
+			let comment = if quoted { 0 } else { self.lexer.comment_len(0) };
+
+			if let (Some(start), b'\n' | b'\r' | 0) = (quote, c) {
+				let span = self.lexer.span_from(start);
+				return Err(ParseError::new(ErrorKind::UnclosedString, "\" not closed", span));
+			} else if comment > 0 || (!quoted && self.lexer.is_whitespace(&c)) {
+				if !s.is_empty() && !s.ends_with(' ') {
+					s.push(' ');
+				}
+
+				self.lexer.skip_blank(comment.max(1));
+				continue;
+			} else if c == b',' && !quoted && nested == 0 {
+				selectors.push(s.trim_end().to_owned());
+				s.clear();
+			} else {
+				// Attribute values may hold anything, including `//`
+				if (c == b'(' || c == b'[') && !quoted {
+					nested += 1;
+				} else if (c == b')' || c == b']') && !quoted {
+					nested = nested.saturating_sub(1);
+				} else if c == b'"' && !quoted {
+					quote = Some(self.lexer.offset());
+				} else if c == b'"' && !s.ends_with('\\') {
+					quote = None;
+				}
+
+				s.push(self.lexer.char_at(0));
+			}
+
+			self.lexer.advance(self.lexer.char_len(0));
+		}
+
+		selectors.push(s.trim_end().to_owned());
+
+		if selectors.iter().any(|s| s.is_empty()) {
+			return Err(unexpected(&self.lexer));
+		}
+
+		if !self.lexer.has_block(depth) {
+			let span = self.lexer.span_from(start);
+			return Err(ParseError::new(ErrorKind::MissingBody, &format!("Selector {} has no block", selectors.join(", ")), span));
+		}
+
+		Ok(Some(NodeKind::Selector(selectors, self.parse_body(depth + 1, true))))
+	}
+
+	fn parse_property(&mut self) -> ParseResult<Option<NodeKind>> {
+		let n = property_len(&self.lexer);
+
+		if n == 0 || self.lexer.at(n) != b':' {
+			return Ok(None);
+		}
+
+		let name = (0..n).map(|i| self.lexer.at(i) as char).collect();
+		self.lexer.advance(n + 1);
+
+		// Space separated values are the arguments of the property, while comma
+		// separated values form a single list with each group as a tuple
+		let mut lists = Vec::new();
+		let mut values = Vec::new();
+
+		loop {
+			self.lexer.skip_spaces();
+
+			if let Some(e) = self.parse_expr()? {
+				values.push(e);
+			} else if !values.is_empty() && self.lexer.try_char(',') {
+				lists.push(values);
+				values = Vec::new();
+				// A trailing comma carries the list on to the lines indented below
+				self.lexer.skip_continuation(self.depth);
+			} else {
+				break;
+			}
+		}
+
+		if values.is_empty() || !is_line_end(&self.lexer) {
+			return Err(unexpected(&self.lexer));
+		}
+
+		if lists.is_empty() {
+			Ok(Some(NodeKind::Property(name, values)))
+		} else {
+			lists.push(values);
+
+			let list: Vec<Expr> = lists
+				.into_iter()
+				.map(|mut values| {
+					if values.len() == 1 {
+						values.remove(0)
+					} else {
+						let span = values[0].span.to(values[values.len() - 1].span);
+						Expr { kind: ExprKind::Tuple(values), span }
+					}
+				})
+				.collect();
+
+			let span = list[0].span.to(list[list.len() - 1].span);
+			Ok(Some(NodeKind::Property(name, vec![Expr { kind: ExprKind::List(list), span }])))
+		}
+	}
+
+	fn parse_atcss(&mut self) -> ParseResult<Option<NodeKind>> {
+		if self.lexer.try_keyword(b"@css") {
+			self.lexer.skip_spaces();
+			if let Some(r) = self.parse_expr()? {
+				return Ok(Some(NodeKind::AtCSS(r)));
+			} else {
+				return Err(unexpected(&self.lexer));
+			}
+		}
+		Ok(None)
+	}
+
+	/// Parses `@data` and the record after it, which may also start on the
+	/// next line.
+	fn parse_atdata(&mut self, depth: usize, selectors: bool) -> ParseResult<Option<NodeKind>> {
+		let span = self.lexer.span_at(0, 5);
+
+		if !self.lexer.try_keyword(b"@data") {
+			return Ok(None);
+		}
+
+		if !selectors {
+			return Err(ParseError::new(ErrorKind::MisplacedData, "@data can only be used in selector and event blocks", span));
+		}
+
+		if is_line_end(&self.lexer) {
+			while self.lexer.try_newline() {}
+
+			if !self.lexer.try_indent(depth + 1)? {
+				return Err(ParseError::new(ErrorKind::MissingBody, "@data has no record", span));
+			}
+
+			// The record is a line of its own, and continues from there
+			self.depth = depth + 1;
+		} else {
+			self.lexer.skip_spaces();
+		}
+
+		let record = match self.parse_record()? {
+			Some(ExprKind::Record(r)) => r,
+			_ => return Err(unexpected(&self.lexer)),
+		};
+
+		self.lexer.skip_spaces();
+
+		if !is_line_end(&self.lexer) {
+			return Err(unexpected(&self.lexer));
+		}
+
+		Ok(Some(NodeKind::AtData(record)))
+	}
+
+	/// Parses an event block such as `@click`, which holds the selectors and
+	/// data to change when the event fires.
+	fn parse_event(&mut self, depth: usize, selectors: bool) -> ParseResult<Option<NodeKind>> {
+		if !selectors || self.lexer.at(0) != b'@' || !self.lexer.at(1).is_ascii_alphabetic() {
+			return Ok(None);
+		}
+
+		let start = self.lexer.clone();
+		self.lexer.advance(1);
+		let event = self.lexer.try_symbol().unwrap();
+
+		if !is_line_end(&self.lexer) {
+			self.lexer = start;
+			return Ok(None);
+		}
+
+		Ok(Some(NodeKind::AtEvent(event, self.parse_body(depth + 1, true))))
+	}
+
+	fn parse_function(&mut self) -> ParseResult<Option<NodeKind>> {
+		if let Some(s) = self.lexer.try_symbol() {
+			let open = self.lexer.span_at(0, 1);
+
+			if self.lexer.try_char('(') {
+				self.lexer.skip_continuation(self.depth);
+
+				let params = if self.lexer.try_char(')') {
+					Vec::new()
+				} else {
+					let mut params = Vec::new();
+
+					loop {
+						if let Some(s) = self.lexer.try_symbol() {
+							params.push(s);
+							self.lexer.skip_continuation(self.depth);
+
+							if self.lexer.try_char(')') {
+								break;
+							} else if !self.lexer.try_char(',') {
+								return Err(unclosed(&self.lexer, '(', open));
+							}
+
+							self.lexer.skip_continuation(self.depth);
+						} else if self.lexer.try_char(')') {
+							break;
+						} else {
+							return Err(unclosed(&self.lexer, '(', open));
+						}
+					}
+
+					params
+				};
+
+				self.lexer.skip_spaces();
+
+				let types = if self.lexer.try_peek(b"::") {
+					self.lexer.skip_spaces();
+
+					if let Some(t) = parse_type(&mut self.lexer)? {
+						let mut types = vec![t];
+
+						while self.lexer.try_arrow() {
+							self.lexer.skip_spaces();
+
+							if let Some(t) = parse_type(&mut self.lexer)? {
+								types.push(t);
+							} else {
+								return Err(unexpected(&self.lexer));
+							}
+						}
+
+						if self.lexer.try_newline() {
+							types
+						} else {
+							return Err(unexpected(&self.lexer));
+						}
+					} else {
+						return Err(unexpected(&self.lexer));
+					}
+				} else {
+					return Err(unexpected(&self.lexer));
+				};
+
+				self.scopes.push(params.clone());
+				let nodes = self.parse_body(1, false);
+				self.scopes.pop();
+				Ok(Some(NodeKind::Function(s, params, types, nodes)))
+			} else {
+				Err(unexpected(&self.lexer))
+			}
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn parse_definition(&mut self) -> ParseResult<Option<NodeKind>> {
+		if !is_definition(&self.lexer) {
+			return Ok(None);
+		}
+
+		let start = self.lexer.offset();
+		self.lexer.advance(1);
+
+		let s = self.lexer.try_symbol().unwrap();
+		let span = self.lexer.span_from(start);
+		self.lexer.skip_spaces();
+		self.lexer.advance(1);
+		self.lexer.skip_spaces();
+
+		match self.parse_expr()? {
+			Some(e) if is_line_end(&self.lexer) => {
+				self.define(&s, span)?;
+				Ok(Some(NodeKind::Definition(s, e)))
+			},
+			_ => Err(unexpected(&self.lexer)),
+		}
+	}
+
+	fn parse_enum(&mut self) -> ParseResult<Option<NodeKind>> {
+		if !self.lexer.try_keyword(b"enum") {
+			return Ok(None);
+		}
+
+		self.lexer.skip_spaces();
+		let start = self.lexer.offset();
+
+		if let Some(s) = self.lexer.try_symbol() {
+			let span = self.lexer.span_from(start);
+			self.lexer.skip_spaces();
+
+			if !self.lexer.try_char('=') {
+				return Err(unexpected(&self.lexer));
+			}
+
+			let mut variants = Vec::new();
+			let mut spans: Vec<Span> = Vec::new();
+
+			loop {
+				self.lexer.skip_spaces();
+
+				let start = self.lexer.offset();
+
+				if let Some(v) = self.lexer.try_symbol() {
+					let span = self.lexer.span_from(start);
+
+					if let Some(i) = variants.iter().position(|w| *w == v) {
+						return Err(
+							ParseError::new(ErrorKind::AlreadyDefined, &format!("Variant {} is listed twice", v), span)
+								.with_label(spans[i], "first listed here")
+						);
+					}
+
+					variants.push(v);
+					spans.push(span);
+				} else {
+					return Err(unexpected(&self.lexer));
+				}
+
+				self.lexer.skip_spaces();
+
+				if self.lexer.try_char('|') {
+					continue;
+				}
+
+				// Alternatives may continue on the indented lines below, usually
+				// with the | aligned under the =
+				let end = self.lexer.clone();
+				self.lexer.skip_continuation(0);
+
+				if !self.lexer.try_char('|') {
+					self.lexer = end;
+					break;
+				}
+			}
+
+			if !is_line_end(&self.lexer) {
+				return Err(unexpected(&self.lexer));
+			}
+
+			if self.is_type(&s) {
+				return Err(ParseError::new(ErrorKind::AlreadyDefined, &format!("Type {} is already defined", s), span));
+			}
+
+			self.enums.insert(s.clone(), variants.clone());
+			Ok(Some(NodeKind::Enum(s, variants)))
+		} else {
+			Err(unexpected(&self.lexer))
+		}
+	}
+
+	fn parse_type_alias(&mut self) -> ParseResult<Option<NodeKind>> {
+		if !self.lexer.try_keyword(b"type") {
+			return Ok(None);
+		}
+
+		self.lexer.skip_spaces();
+		let start = self.lexer.offset();
+
+		if let Some(s) = self.lexer.try_symbol() {
+			let span = self.lexer.span_from(start);
+			self.lexer.skip_spaces();
+
+			if !self.lexer.try_char('=') {
+				return Err(unexpected(&self.lexer));
+			}
+
+			self.lexer.skip_spaces();
+			let type_start = self.lexer.offset();
+
+			if let Some(mut t) = parse_type(&mut self.lexer)? {
+				// Like a signature, an alias may be an arrow chain without parentheses
+				self.lexer.skip_spaces();
+
+				if self.lexer.try_arrow() {
+					let mut types = vec![t];
+
+					loop {
+						self.lexer.skip_spaces();
+
+						match parse_type(&mut self.lexer)? {
+							Some(t) => types.push(t),
+							None => return Err(unexpected(&self.lexer)),
+						}
+
+						self.lexer.skip_spaces();
+
+						if !self.lexer.try_arrow() {
+							break;
+						}
+					}
+
+					t = Type { kind: TypeKind::Function(types), span: self.lexer.span_from(type_start) };
+				}
+
+				if !is_line_end(&self.lexer) {
+					return Err(unexpected(&self.lexer));
+				}
+
+				if self.is_type(&s) {
+					return Err(ParseError::new(ErrorKind::AlreadyDefined, &format!("Type {} is already defined", s), span));
+				}
+
+				self.aliases.insert(s.clone(), t.clone());
+				Ok(Some(NodeKind::TypeAlias(s, t)))
+			} else {
+				Err(unexpected(&self.lexer))
+			}
+		} else {
+			Err(unexpected(&self.lexer))
+		}
+	}
+
+	fn parse_operator(&mut self) -> ParseResult<Option<NodeKind>> {
+		let associativity =
+			if self.lexer.try_keyword(b"infixl") {
+				Associativity::Left
+			} else if self.lexer.try_keyword(b"infixr") {
+				Associativity::Right
+			} else if self.lexer.try_keyword(b"infix") {
+				Associativity::None
+			} else {
+				return Ok(None);
+			};
+
+		self.lexer.skip_spaces();
+		let start = self.lexer.offset();
+
+		let precedence = match self.lexer.try_number()? {
+			Some(n) if n.fract() == 0.0 && (1.0..=9.0).contains(&n) => n as u8,
+			Some(_) => {
+				let span = self.lexer.span_from(start);
+				return Err(ParseError::new(ErrorKind::InvalidOperator, "Precedence must be a whole number from 1 to 9", span));
+			},
+			None => return Err(unexpected(&self.lexer)),
+		};
+
+		self.lexer.skip_spaces();
+		let start = self.lexer.offset();
+
+		let op = match self.lexer.try_operator_chars() {
+			Some(op) => op,
+			None => return Err(unexpected(&self.lexer)),
+		};
+		let span = self.lexer.span_from(start);
+
+		// `=` and `|` are part of definitions and enums
+		if lexer::OPERATORS.contains(&op.as_str()) || op == "=" || op == "|" {
+			return Err(ParseError::new(ErrorKind::InvalidOperator, &format!("{} is built in and cannot be redeclared", op), span));
+		}
+
+		if op.contains("//") || op.contains("/*") {
+			return Err(ParseError::new(ErrorKind::InvalidOperator, &format!("{} would start a comment", op), span));
+		}
+
+		// `&` and `~/` are element references
+		if op.starts_with('&') || op.starts_with('~') {
+			return Err(ParseError::new(ErrorKind::InvalidOperator, &format!("{} would clash with an element reference", op), span));
+		}
+
+		if self.operators.contains_key(&op) {
+			return Err(ParseError::new(ErrorKind::AlreadyDefined, &format!("Operator {} is already declared", op), span));
+		}
+
+		self.lexer.skip_spaces();
+
+		if !self.lexer.try_char('=') {
+			return Err(unexpected(&self.lexer));
+		}
+
+		self.lexer.skip_spaces();
+
+		let function = match self.lexer.try_symbol() {
+			Some(f) if is_line_end(&self.lexer) => f,
+			_ => return Err(unexpected(&self.lexer)),
+		};
+
+		let operator = Operator { precedence, associativity, function: Some(function) };
+		self.operators.insert(op.clone(), operator.clone());
+		Ok(Some(NodeKind::Operator(op, operator)))
+	}
+
+	/// Resolves the types in signatures once the whole file has been read, so
+	/// that types may be used before they are declared. Each cycle of aliases
+	/// is reported once, at the first alias in it, and is left unresolved.
+	fn resolve_types(&mut self, ast: &mut [Node]) {
+		let mut cyclic = Vec::new();
+
+		for node in ast.iter() {
+			if let NodeKind::TypeAlias(s, t) = &node.kind {
+				if cyclic.contains(s) {
+					continue;
+				}
+
+				let mut visiting = vec![s.clone()];
+
+				if let Err(e) = self.resolve_type(t, &mut visiting) {
+					self.errors.push(e);
+					cyclic.extend(visiting);
+				}
+			}
+		}
+
+		for s in &cyclic {
+			self.aliases.remove(s);
+		}
+
+		for node in ast.iter_mut() {
+			if let NodeKind::Function(_, _, types, _) = &mut node.kind {
+				for t in types.iter_mut() {
+					match self.resolve_type(t, &mut Vec::new()) {
+						Ok(resolved) => *t = resolved,
+						Err(e) => self.errors.push(e),
+					}
+				}
+			}
+		}
+	}
+
+	fn parse_root_node(&mut self) -> ParseResult<Option<Node>> {
+		self.depth = 0;
+		check_indent(&self.lexer, 0)?;
+		self.lexer.skip_spaces();
+		let start = self.lexer.offset();
+
+		let kind =
+			if let Some(n) = self.parse_enum()? {
+				n
+			} else if let Some(n) = self.parse_type_alias()? {
+				n
+			} else if let Some(n) = self.parse_operator()? {
+				n
+			} else if let Some(n) = self.parse_selector(0)? {
+				n
+			} else if let Some(n) = self.parse_function()? {
+				n
+			} else if let Some(n) = self.parse_definition()? {
+				n
+			} else if is_line_end(&self.lexer) {
+				// A comment left open runs to the end of the input
+				if !self.lexer.try_newline() {
+					self.lexer.skip_whitespace();
+				}
+
+				return Ok(None);
+			} else {
+				return Err(unexpected(&self.lexer));
+			};
+
+		Ok(Some(Node { kind, span: self.lexer.span_from(start), comments: Vec::new() }))
+	}
+}
+
+/// Parses as much of the input as possible, returning every error found
+/// alongside the nodes that could still be parsed.
+pub fn parse_partial(input: &[u8], file: FileId) -> (Vec<Node>, Vec<ParseError>) {
+	let mut parser = Parser {
+		lexer: Lexer::new(input, file),
+		scopes: vec![Vec::new()],
+		enums: FnvHashMap::default(),
+		aliases: FnvHashMap::default(),
+		operators: builtin_operators(),
+		depth: 0,
+		brackets: 0,
+		errors: Vec::new(),
+	};
+	let mut ast = Vec::new();
+
+	while parser.lexer.has_left() {
+		match parser.parse_root_node() {
+			Ok(Some(n)) => ast.push(n),
+			Ok(None) => (),
+			Err(e) => parser.recover(e, 0),
+		}
+	}
+
+	parser.resolve_types(&mut ast);
+	parser.errors.sort_by_key(|e| e.span.start);
+
+	let (comments, unclosed) = parser.lexer.take_comments();
+	parser.errors.extend(unclosed);
+	attach_comments(&mut ast, comments, input);
+	(ast, parser.errors)
+}
+
+/// Visits every node, each before the nodes nested inside it.
+fn walk(nodes: &mut [Node], f: &mut impl FnMut(&mut Node)) {
+	for n in nodes {
+		f(n);
+
+		match &mut n.kind {
+			NodeKind::Selector(_, body) | NodeKind::Function(_, _, _, body) | NodeKind::AtEvent(_, body) => walk(body, f),
+			NodeKind::If(_, then, otherwise) => {
+				walk(then, f);
+				walk(otherwise, f);
+			},
+			_ => (),
+		}
+	}
+}
+
+/// Gives each comment to the last node starting before it on its line, or
+/// else to the next node, so that comments above a node document it. A
+/// comment indented deeper than the next node, such as one ending a body, goes
+/// back to the last node indented no deeper than it.
+fn attach_comments(ast: &mut [Node], comments: Vec<Comment>, input: &[u8]) {
+	// Nodes are visited in the order they start, as are the comments
+	let mut starts = Vec::new();
+	walk(ast, &mut |n| starts.push(n.span.start));
+
+	let lines: Vec<usize> = std::iter::once(0)
+		.chain(input.iter().enumerate().filter(|(_, c)| **c == b'\n').map(|(i, _)| i + 1))
+		.collect();
+	let line = |offset: usize| lines.partition_point(|start| *start <= offset) - 1;
+	let column = |offset: usize| offset - lines[line(offset)];
+
+	let mut owners = vec![Vec::new(); starts.len()];
+	// The nodes before the comment that no later node is indented less than,
+	// from the least indented up
+	let mut outer: Vec<usize> = Vec::new();
+	let mut next = 0;
+
+	for c in comments {
+		while next < starts.len() && starts[next] < c.span.start {
+			while outer.last().is_some_and(|i| column(starts[*i]) >= column(starts[next])) {
+				outer.pop();
+			}
+
+			outer.push(next);
+			next += 1;
+		}
+
+		let indent = column(c.span.start);
+		let after = Some(next).filter(|i| *i < starts.len());
+
+		let owner = next.checked_sub(1)
+			.filter(|i| line(starts[*i]) == line(c.span.start))
+			.or_else(|| after.filter(|i| column(starts[*i]) >= indent))
+			.or_else(|| outer[..outer.partition_point(|i| column(starts[*i]) <= indent)].last().copied())
+			.or(after);
+
+		if let Some(i) = owner {
+			owners[i].push(c);
+		}
+	}
+
+	let mut owners = owners.into_iter();
+	walk(ast, &mut |n| n.comments = owners.next().unwrap());
+}
+
+pub fn parse(input: &[u8], file: FileId) -> Result<Vec<Node>, Vec<ParseError>> {
+	let (ast, errors) = parse_partial(input, file);
+
+	if errors.is_empty() {
+		Ok(ast)
+	} else {
+		Err(errors)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Writes an expression out with its grouping made explicit.
+	fn show(e: &Expr) -> String {
+		match &e.kind {
+			ExprKind::Number(n) => n.to_string(),
+			ExprKind::Dimension(n, unit) => format!("{}{}", n, unit),
+			ExprKind::Symbol(s) => s.clone(),
+			ExprKind::Variable(s) => format!("${}", s),
+			ExprKind::String(s) => format!("{:?}", s),
+			ExprKind::BinaryOp(op, a, b) => format!("({} {} {})", op, show(a), show(b)),
+			ExprKind::UnaryOp(op, a) => format!("({} {})", op, show(a)),
+			ExprKind::Interpolated(parts) => format!("\"{}\"", parts.iter().map(|p| match p {
+				StringPart::Text(t) => t.clone(),
+				StringPart::Expr(e) => format!("${{{}}}", show(e)),
+			}).collect::<String>()),
+			ExprKind::Call(f, args) => format!("{}({})", f, args.iter().map(show).collect::<Vec<_>>().join(", ")),
+			kind => format!("{:?}", kind),
+		}
+	}
+
+	/// Parses `input`, which has to be free of errors.
+	fn parse_ok(input: &str) -> Vec<Node> {
+		match parse(input.as_bytes(), FileId::default()) {
+			Ok(ast) => ast,
+			Err(errors) => panic!("{:?}", errors),
+		}
+	}
+
+	/// Returns the value of the last definition in `input`.
+	fn definition(input: &str) -> String {
+		match &parse_ok(input).last().unwrap().kind {
+			NodeKind::Definition(_, e) => show(e),
+			kind => panic!("expected a definition, found {:?}", kind),
+		}
+	}
+
+	/// Returns the name and values of the only property in `input`.
+	fn property(input: &str) -> (String, Vec<String>) {
+		match &parse_ok(input)[0].kind {
+			NodeKind::Selector(_, body) => match &body[0].kind {
+				NodeKind::Property(name, values) => (name.clone(), values.iter().map(show).collect()),
+				kind => panic!("expected a property, found {:?}", kind),
+			},
+			kind => panic!("expected a selector, found {:?}", kind),
+		}
+	}
+
+	/// Lists what the nodes hold, with nested nodes after their parent.
+	fn outline(nodes: &[Node]) -> Vec<String> {
+		let mut lines = Vec::new();
+
+		for n in nodes {
+			match &n.kind {
+				NodeKind::Selector(s, body) => {
+					lines.push(s.join(", "));
+					lines.extend(outline(body).into_iter().map(|l| format!("  {}", l)));
+				},
+				NodeKind::Property(name, _) => lines.push(format!("{}:", name)),
+				NodeKind::Definition(name, _) => lines.push(format!("${} =", name)),
+				kind => lines.push(format!("{:?}", kind)),
+			}
+		}
+
+		lines
+	}
+
+	#[test]
+	fn multiplication_binds_tighter() {
+		assert_eq!(definition("$x = 1 + 2 * 3"), "(+ 1 (* 2 3))");
+	}
+
+	#[test]
+	fn subtraction_is_left_associative() {
+		assert_eq!(definition("$x = 10 - 2 - 3"), "(- (- 10 2) 3)");
+	}
+
+	#[test]
+	fn minus_before_a_number_starts_a_value() {
+		assert_eq!(property("p\n\tmargin: 0 -1px"), ("margin".into(), vec!["0".into(), "-1px".into()]));
+	}
+
+	#[test]
+	fn dashes_are_part_of_names() {
+		assert_eq!(property("p\n\tfont-weight: bold"), ("font-weight".into(), vec!["bold".into()]));
+		assert_eq!(property("p\n\ttransition: font-weight 1s").1, vec!["font-weight", "1s"]);
+	}
+
+	#[test]
+	fn concatenation() {
+		assert_eq!(definition("$a = \"a\"\n$b = \"b\"\n$c = $a ++ $b"), "(++ $a $b)");
+	}
+
+	#[test]
+	fn brackets_continue_on_indented_lines() {
+		assert_eq!(
+			outline(&parse_ok("$r = {\n\ta: 1,\n\tb: [1,\n\t\t2]\n}\n.c\n\tmargin: (1em,\n\t\t2em)\n\tpadding: 0")),
+			vec!["$r =", ".c", "  margin:", "  padding:"],
+		);
+	}
+
+	#[test]
+	fn leftovers_after_a_node_are_reported() {
+		let input = ".a\n\t$y = 1 .b\n\t@css { a: 1 } junk\n\twidth: 1";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec![".a", "  width:"]);
+		assert_eq!(errors.iter().map(|e| (e.kind, e.span.start)).collect::<Vec<_>>(), vec![
+			(ErrorKind::UnexpectedSymbol, input.find(".b").unwrap()),
+			(ErrorKind::UnexpectedSymbol, input.find("junk").unwrap()),
+		]);
+	}
+
+	#[test]
+	fn unclosed_brackets_stop_at_their_line() {
+		let input = ".s\n\t.t\n\t\theight: (\n\t\twidth: 1\n$r = { a: 1\n.c\n\tcolor: red";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec![".s", "  .t", "    width:", ".c", "  color:"]);
+		assert_eq!(errors.iter().map(|e| (e.kind, e.span.start)).collect::<Vec<_>>(), vec![
+			(ErrorKind::UnclosedBracket, input.find('(').unwrap()),
+			(ErrorKind::UnclosedBracket, input.find('{').unwrap()),
+		]);
+	}
+
+	#[test]
+	fn operators_do_not_continue_onto_the_next_line() {
+		let input = "$a = 1 +\n$b = 2";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec!["$b ="]);
+		assert_eq!(errors[0].kind, ErrorKind::UnexpectedEnd);
+	}
+
+	/// Returns the text of the comments on each node, nested nodes included.
+	fn comments(nodes: &[Node]) -> Vec<Vec<String>> {
+		let mut all = Vec::new();
+
+		for n in nodes {
+			all.push(n.comments.iter().map(|c| c.text.trim().to_string()).collect());
+
+			if let NodeKind::Selector(_, body) | NodeKind::Function(_, _, _, body) = &n.kind {
+				all.extend(comments(body));
+			}
+		}
+
+		all
+	}
+
+	#[test]
+	fn custom_operators_group_by_their_associativity() {
+		assert_eq!(definition("infixl 6 <+> = f\n$x = 1 <+> 2 <+> 3"), "f(f(1, 2), 3)");
+		assert_eq!(definition("infixr 6 <+> = f\n$x = 1 <+> 2 <+> 3"), "f(1, f(2, 3))");
+		assert_eq!(definition("infixl 8 <+> = f\n$x = 1 * 2 <+> 3"), "(* 1 f(2, 3))");
+	}
+
+	#[test]
+	fn non_associative_operators_cannot_be_chained() {
+		let input = "infix 6 <+> = f\n$x = 1 <+> 2 <+> 3\n$y = (1 <+> 2) <+> 3";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec!["Operator(\"<+>\", Operator { precedence: 6, associativity: None, function: Some(\"f\") })", "$y ="]);
+		assert_eq!(errors.len(), 1);
+		assert_eq!((errors[0].kind, errors[0].kind.code()), (ErrorKind::NonAssociative, "G0009"));
+	}
+
+	#[test]
+	fn element_references_cannot_be_operators() {
+		for op in &["&", "&&", "~", "~/", "~>"] {
+			let input = format!("infixl 6 {} = f\n.a\n\twidth: 1 &.width\n\theight: 1 ~/.width", op);
+			let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+			assert_eq!(outline(&ast), vec![".a", "  width:", "  height:"]);
+			assert_eq!(errors.iter().map(|e| (e.kind, e.span.start)).collect::<Vec<_>>(), vec![(ErrorKind::InvalidOperator, 9)]);
+		}
+	}
+
+	/// Returns the kind and position of every error in `input`.
+	fn errors(input: &str) -> Vec<(ErrorKind, usize, usize)> {
+		parse_partial(input.as_bytes(), FileId::default()).1.iter().map(|e| (e.kind, e.span.start, e.span.end)).collect()
+	}
+
+	#[test]
+	fn the_first_indented_line_sets_the_unit() {
+		for unit in &["\t", "  ", "    "] {
+			let input = format!(".a\n{0}.b\n{0}{0}color: red\n{0}width: 1", unit);
+			assert_eq!(outline(&parse_ok(&input)), vec![".a", "  .b", "    color:", "  width:"]);
+		}
+
+		assert_eq!(errors(".a\n   color: red"), vec![(ErrorKind::InconsistentIndent, 3, 6)]);
+	}
+
+	#[test]
+	fn indentation_has_to_match_the_unit() {
+		assert_eq!(errors(".a\n\tcolor: red\n  width: 1\n.b\n\t \theight: 1"), vec![
+			(ErrorKind::InconsistentIndent, 15, 17),
+			(ErrorKind::InconsistentIndent, 29, 32),
+		]);
+		assert_eq!(errors(".a\n    color: red\n      width: 1"), vec![(ErrorKind::InconsistentIndent, 18, 24)]);
+	}
+
+	#[test]
+	fn lines_cannot_be_indented_too_deep() {
+		let input = ".a\n\tcolor: red\n\t\twidth: 1\n\theight: 1\n$x = 1\n\t.b\n.c\n\t\t\tcolor: red";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec![".a", "  color:", "  height:", "$x =", ".c"]);
+		assert_eq!(errors.iter().map(|e| (e.kind, e.span.start, e.span.end)).collect::<Vec<_>>(), vec![
+			(ErrorKind::UnexpectedIndent, 15, 17),
+			(ErrorKind::UnexpectedIndent, 44, 45),
+			(ErrorKind::UnexpectedIndent, 51, 54),
+		]);
+	}
+
+	/// Returns the fields of every `@data` in `input`, sorted by name.
+	fn data(nodes: &[Node]) -> Vec<Vec<(String, String)>> {
+		let mut all = Vec::new();
+
+		for n in nodes {
+			match &n.kind {
+				NodeKind::AtData(record) => {
+					let mut fields: Vec<_> = record.iter().map(|(k, v)| (k.clone(), show(v))).collect();
+					fields.sort();
+					all.push(fields);
+				},
+				NodeKind::Selector(_, body) | NodeKind::AtEvent(_, body) => all.extend(data(body)),
+				_ => (),
+			}
+		}
+
+		all
+	}
+
+	#[test]
+	fn data_records() {
+		let ast = parse_ok(".a\n\t@data { open: false }\n\t@data\n\t\t{ pad: 5\n\t\t, gap: 1px\n\t\t}\n\t@click\n\t\t@data { pad: 10 }");
+
+		assert_eq!(data(&ast), vec![
+			vec![("open".into(), "Bool(false)".into())],
+			vec![("gap".into(), "1px".into()), ("pad".into(), "5".into())],
+			vec![("pad".into(), "10".into())],
+		]);
+	}
+
+	#[test]
+	fn data_needs_a_record_in_a_selector() {
+		assert_eq!(errors("f(a) :: Number -> Number\n\t@data { pad: 1 }\n\t$a"), vec![(ErrorKind::MisplacedData, 26, 31)]);
+		assert_eq!(errors(".a\n\t@data\n\tcolor: red"), vec![(ErrorKind::MissingBody, 4, 9)]);
+		assert_eq!(errors(".a\n\t@data 5"), vec![(ErrorKind::UnexpectedSymbol, 10, 11)]);
+	}
+
+	#[test]
+	fn byte_order_marks_and_crlf_line_endings() {
+		let ast = parse_ok("\u{feff}.café\r\n\tcontent: \"☕\"\r\n\r\n.b\r\n\tcolor: red");
+
+		assert_eq!(outline(&ast), vec![".café", "  content:", ".b", "  color:"]);
+		assert_eq!(ast[0].span.start, 3);
+	}
+
+	#[test]
+	fn string_escapes() {
+		assert_eq!(definition(r#"$s = "caf\e9  \"q\" \\ \n\t \$x \41 B""#), format!("{:?}", "café \"q\" \\ \n\t $x AB"));
+		assert_eq!(definition("$s = \"a\\\nb\""), format!("{:?}", "ab"));
+	}
+
+	#[test]
+	fn string_interpolation() {
+		assert_eq!(definition("$n = 2\n$s = \"${$n * 2}px\""), "\"${(* $n 2)}px\"");
+		assert_eq!(definition("$n = 2\n$s = \"$n ${$n}-x ${\"}\" ++ \"a\"} $\""), "\"${$n} ${$n}-x ${(++ \"}\" \"a\")} $\"");
+		assert_eq!(definition("$s = \"${\"a\"}\""), "\"${\"a\"}\"");
+	}
+
+	#[test]
+	fn strings_end_at_the_line_break() {
+		let input = ".a\n\tcontent: \"abc\n\tcolor: red\n$s = \"${1} x";
+		let (ast, errors) = parse_partial(input.as_bytes(), FileId::default());
+
+		assert_eq!(outline(&ast), vec![".a", "  color:"]);
+		assert_eq!(errors.iter().map(|e| (e.kind, e.span.start, e.span.end)).collect::<Vec<_>>(), vec![
+			(ErrorKind::UnclosedString, 13, 17),
+			(ErrorKind::UnclosedString, 35, 42),
+		]);
+	}
+
+	/// Returns the selector list of the first node in `input`.
+	fn selectors(input: &str) -> Vec<String> {
+		match &parse_ok(input)[0].kind {
+			NodeKind::Selector(s, _) => s.clone(),
+			kind => panic!("expected a selector, found {:?}", kind),
+		}
+	}
+
+	#[test]
+	fn commas_in_brackets_do_not_split_selectors() {
+		assert_eq!(selectors(".a:not(.b, .c), a:is(h1, h2) > span\n\tcolor: red"), vec![".a:not(.b, .c)", "a:is(h1, h2) > span"]);
+		assert_eq!(selectors("a[title=\"a, b\"], b\n\tcolor: red"), vec!["a[title=\"a, b\"]", "b"]);
+	}
+
+	#[test]
+	fn block_comments_can_start_a_line() {
+		let ast = parse_ok("/* c */ .a\n\t/* note */ color: red\nf(a) :: Number -> Number\n\t/* d */ $a");
+
+		assert_eq!(outline(&ast)[..2], [".a", "  color:"]);
+		assert_eq!(comments(&ast), vec![vec!["c"], vec!["note"], vec![], vec!["d"]]);
+	}
+
+	#[test]
+	fn comments_attach_to_the_nearest_node() {
+		let ast = parse_ok("// a\n.a // b\n\t// c\n\tcolor: red /* d */\n\n// e\n$x = 1");
+
+		assert_eq!(comments(&ast), vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+	}
 }
@@ -0,0 +1,450 @@
+/*
+ *  Copyright (c) 2020, GiraffeKey
+ *
+ *  All rights reserved.
+ *
+ *  Redistribution and use in source and binary forms, with or without
+ *  modification, are permitted provided that the following conditions are met:
+ *
+ *  Redistributions of source code must retain the above copyright
+ *  notice, this list of conditions and the following disclaimer.
+ *
+ *  Redistributions in binary form must reproduce the above
+ *  copyright notice, this list of conditions and the following
+ *  disclaimer in the documentation and/or other materials provided
+ *  with the distribution.
+ *
+ *  Neither the name of GiraffeKey nor the names of other
+ *  contributors may be used to endorse or promote products derived
+ *  from this software without specific prior written permission.
+ *
+ *  THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+ *  "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+ *  LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+ *  A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+ *  OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ *  SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+ *  LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+ *  DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+ *  THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+ *  (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ *  OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::collections::VecDeque;
+use crate::ast::{FileId, Span};
+use crate::error::ParseResult;
+use super::lexer::{self, Lexer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+	Number(f32),
+	Dimension(f32, String),
+	String(String),
+	Hex(String),
+	Bool(bool),
+	Symbol(String),
+	Keyword(String),
+	/// A name starting with `$`.
+	Variable(String),
+	/// A name starting with `@`, such as `@css`.
+	At(String),
+	Operator(String),
+	Punct(char),
+	Newline,
+	Indent,
+	Dedent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+	pub kind: TokenKind,
+	pub span: Span,
+}
+
+/// An iterator over the tokens of a source file.
+///
+/// Indentation is reported as `Indent` and `Dedent` tokens at the start of a
+/// line, and every non-blank line ends with a `Newline`. Comments are skipped,
+/// so lines holding only comments count as blank. Errors are returned in place
+/// of the token they were found in, after which lexing carries on.
+///
+/// Lines are joined the way the parser joins them: inside brackets, after a
+/// trailing comma and before enum alternatives, an indented line below goes
+/// on with the same line.
+pub struct Tokens<'a> {
+	lexer: Lexer<'a>,
+	/// The operators known so far, built in and declared.
+	operators: Vec<String>,
+	/// Whether the current line is an operator declaration.
+	declaring: bool,
+	/// Whether the current line declares an enum.
+	declaring_enum: bool,
+	/// The brackets left open so far, innermost last.
+	brackets: Vec<(char, Span)>,
+	/// Whether the last token was a comma.
+	comma: bool,
+	/// Whether no token has been read on this line yet.
+	first: bool,
+	indent: usize,
+	pending: VecDeque<Token>,
+	line_start: bool,
+	done: bool,
+}
+
+pub fn tokenize(input: &[u8], file: FileId) -> Tokens<'_> {
+	Tokens {
+		lexer: Lexer::new(input, file),
+		operators: super::builtin_operators().into_keys().collect(),
+		declaring: false,
+		declaring_enum: false,
+		brackets: Vec::new(),
+		comma: false,
+		first: true,
+		indent: 0,
+		pending: VecDeque::new(),
+		line_start: true,
+		done: false,
+	}
+}
+
+impl<'a> Tokens<'a> {
+	fn push(&mut self, kind: TokenKind, span: Span) {
+		self.pending.push_back(Token { kind, span });
+	}
+
+	/// Skips blank lines and the indentation of the next line, queueing up
+	/// the change in indentation.
+	fn start_line(&mut self) -> ParseResult<()> {
+		self.line_start = false;
+
+		loop {
+			if !self.lexer.has_left() {
+				let span = self.lexer.span_from(self.lexer.offset());
+
+				while self.indent > 0 {
+					self.push(TokenKind::Dedent, span);
+					self.indent -= 1;
+				}
+
+				self.done = true;
+				return Ok(());
+			}
+
+			let start = self.lexer.offset();
+
+			if let Some(indent) = self.lexer.skip_indent()? {
+				let span = self.lexer.span_from(start);
+
+				while self.indent < indent {
+					self.push(TokenKind::Indent, span);
+					self.indent += 1;
+				}
+
+				while self.indent > indent {
+					self.push(TokenKind::Dedent, span);
+					self.indent -= 1;
+				}
+
+				return Ok(());
+			}
+
+			// Lines holding only comments are skipped like blank lines, and
+			// the last one is left to `next` if it has a comment left open
+			if self.lexer.try_newline() {
+				continue;
+			} else if skip_comments(&mut self.lexer.clone()).is_err() {
+				return Ok(());
+			}
+
+			self.lexer.skip_whitespace();
+		}
+	}
+
+	fn token(&mut self) -> ParseResult<TokenKind> {
+		let c = self.lexer.at(0);
+
+		if c.is_ascii_digit() || (c == b'.' && self.lexer.at(1).is_ascii_digit()) {
+			let n = self.lexer.try_number()?.unwrap();
+
+			return Ok(match self.lexer.try_unit()? {
+				Some(unit) => TokenKind::Dimension(n, unit),
+				None => TokenKind::Number(n),
+			});
+		}
+
+		if let Some(s) = self.lexer.try_string()? {
+			return Ok(TokenKind::String(s));
+		}
+
+		// Names with leading dashes, as read by the parser: custom properties
+		// anywhere, and prefixed names such as `-webkit-box` as properties
+		let n = super::property_len(&self.lexer);
+
+		if c == b'-' && n > 0 && (self.lexer.at(1) == b'-' || (self.first && self.lexer.at(n) == b':')) {
+			let name = (0..n).map(|i| self.lexer.at(i) as char).collect();
+			self.lexer.advance(n);
+			return Ok(TokenKind::Symbol(name));
+		}
+
+		if self.lexer.is_hex() {
+			return Ok(TokenKind::Hex(self.lexer.try_hex()?.unwrap()));
+		}
+
+		if (c == b'$' || c == b'@') && self.lexer.at(1).is_ascii_alphabetic() {
+			self.lexer.advance(1);
+			let s = self.lexer.try_symbol().unwrap();
+			return Ok(if c == b'$' { TokenKind::Variable(s) } else { TokenKind::At(s) });
+		}
+
+		if let Some(s) = self.lexer.try_symbol() {
+			return Ok(match s.as_str() {
+				"true" => TokenKind::Bool(true),
+				"false" => TokenKind::Bool(false),
+				"infixl" | "infixr" | "infix" => {
+					self.declaring = true;
+					TokenKind::Keyword(s)
+				},
+				"enum" => {
+					self.declaring_enum = true;
+					TokenKind::Keyword(s)
+				},
+				s if lexer::KEYWORDS.contains(&s.as_bytes()) => TokenKind::Keyword(s.into()),
+				// Word operators such as `and`
+				s if self.operators.iter().any(|op| op == s) => TokenKind::Operator(s.into()),
+				_ => TokenKind::Symbol(s),
+			});
+		}
+
+		// The operator being declared is read whole, since it is not known yet
+		if self.declaring {
+			if let Some(op) = self.lexer.try_operator_chars() {
+				self.declaring = false;
+				self.operators.push(op.clone());
+				return Ok(TokenKind::Operator(op));
+			}
+		}
+
+		if let Some(op) = self.lexer.peek_operator(0, self.operators.iter().map(String::as_str)) {
+			self.lexer.advance(op.len());
+			return Ok(TokenKind::Operator(op.into()));
+		}
+
+		let span = self.lexer.span_at(0, 1);
+		let c = self.lexer.char_at(0);
+		self.lexer.advance(self.lexer.char_len(0));
+
+		match c {
+			'(' | '[' | '{' => self.brackets.push((c, span)),
+			')' | ']' | '}' => {
+				self.brackets.pop();
+			},
+			_ => (),
+		}
+
+		Ok(TokenKind::Punct(c))
+	}
+
+	/// Moves onto the next line if it goes on with this one, as the parser
+	/// would read it.
+	fn continue_line(&mut self) -> bool {
+		let end = self.lexer.clone();
+
+		if !self.brackets.is_empty() || self.comma {
+			self.lexer.skip_continuation(self.indent);
+
+			if !super::is_line_end(&self.lexer) {
+				return true;
+			}
+		}
+
+		if self.declaring_enum {
+			self.lexer.skip_continuation(0);
+
+			if self.lexer.at(0) == b'|' {
+				return true;
+			}
+		}
+
+		self.lexer = end;
+		false
+	}
+}
+
+/// Skips spaces and comments, failing on a comment that is never closed.
+fn skip_comments(lexer: &mut Lexer) -> ParseResult<()> {
+	loop {
+		while [b' ', b'\t'].contains(&lexer.at(0)) {
+			lexer.advance(1);
+		}
+
+		if lexer.try_comment()?.is_none() {
+			return Ok(());
+		}
+	}
+}
+
+impl<'a> Iterator for Tokens<'a> {
+	type Item = ParseResult<Token>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(t) = self.pending.pop_front() {
+				return Some(Ok(t));
+			}
+
+			if self.done {
+				return None;
+			}
+
+			if self.line_start {
+				// A badly indented line keeps the indentation of the last one
+				if let Err(e) = self.start_line() {
+					self.lexer.skip_spaces();
+					return Some(Err(e));
+				}
+
+				continue;
+			}
+
+			if let Err(e) = skip_comments(&mut self.lexer) {
+				self.lexer.advance(e.span.end - e.span.start);
+				return Some(Err(e));
+			}
+
+			let start = self.lexer.offset();
+
+			if super::is_line_end(&self.lexer) {
+				if self.continue_line() {
+					continue;
+				}
+
+				// Like the parser, a bracket still open is reported once the
+				// line cannot go on
+				if let Some((c, span)) = self.brackets.pop() {
+					self.brackets.clear();
+					return Some(Err(super::unclosed(&self.lexer, c, span)));
+				}
+			}
+
+			// The last line may not end in a line break, but still gets a Newline
+			if !self.lexer.has_left() || self.lexer.try_newline() {
+				self.line_start = true;
+				self.declaring = false;
+				self.declaring_enum = false;
+				self.comma = false;
+				self.first = true;
+				return Some(Ok(Token { kind: TokenKind::Newline, span: self.lexer.span_from(start) }));
+			}
+
+			return match self.token() {
+				Ok(kind) => {
+					self.comma = kind == TokenKind::Punct(',');
+					self.first = false;
+					Some(Ok(Token { kind, span: self.lexer.span_from(start) }))
+				},
+				Err(e) => {
+					// Skip past the bad input so the next call makes progress
+					let end = e.span.end.max(start + 1);
+					self.lexer.advance(end - self.lexer.offset().min(end));
+					Some(Err(e))
+				},
+			};
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Writes the tokens of `input` out a line at a time, with `>` for an
+	/// Indent and `<` for a Dedent.
+	fn lines(input: &str) -> Vec<String> {
+		let mut lines = Vec::new();
+		let mut line = Vec::new();
+
+		for t in tokenize(input.as_bytes(), FileId::default()) {
+			line.push(match t.unwrap().kind {
+				TokenKind::Number(n) => n.to_string(),
+				TokenKind::Dimension(n, unit) => format!("{}{}", n, unit),
+				TokenKind::String(s) => format!("{:?}", s),
+				TokenKind::Hex(s) => format!("#{}", s),
+				TokenKind::Bool(b) => format!("bool:{}", b),
+				TokenKind::Symbol(s) | TokenKind::Operator(s) => s,
+				TokenKind::Keyword(s) => format!("kw:{}", s),
+				TokenKind::Variable(s) => format!("${}", s),
+				TokenKind::At(s) => format!("@{}", s),
+				TokenKind::Punct(c) => c.to_string(),
+				TokenKind::Indent => ">".into(),
+				TokenKind::Dedent => "<".into(),
+				TokenKind::Newline => {
+					lines.push(line.join(" "));
+					line.clear();
+					continue;
+				},
+			});
+		}
+
+		if !line.is_empty() {
+			lines.push(line.join(" "));
+		}
+
+		lines
+	}
+
+	#[test]
+	fn readme_example() {
+		let input = include_str!("../../README.md");
+		let start = input.find("```glaze\n").unwrap() + 9;
+		let end = start + input[start..].find("```").unwrap();
+
+		assert_eq!(lines(&input[start..end]), vec![
+			". example",
+			"> img",
+			"> @data",
+			"> { pad : 5 }",
+			"< padding : & . data . pad ++ px",
+			"width : & . data . pad * 20px",
+			"height : & . width",
+			"< button",
+			"> @click",
+			"> & ~ img",
+			"> @data",
+			"> { pad : & . data . pad * 2 ++ px }",
+			"< < < < <",
+		]);
+	}
+
+	#[test]
+	fn keywords_are_whole_words() {
+		assert_eq!(lines("$a = trueColor true if ifs"), vec!["$a = trueColor bool:true kw:if ifs"]);
+	}
+
+	#[test]
+	fn indentation() {
+		assert_eq!(lines("a\n\tb\n\n\t\tc\n\t// d\nd"), vec!["a", "> b", "> c", "< < d"]);
+	}
+
+	#[test]
+	fn continuation_lines() {
+		assert_eq!(lines("a\n\tb: (1,\n\t\t2)\n\tc: 1,\n\t\t2\n\td: 3"), vec![
+			"a",
+			"> b : ( 1 , 2 )",
+			"c : 1 , 2",
+			"d : 3",
+			"<",
+		]);
+		assert_eq!(lines("enum A = B\n\t| C\n$x = 1"), vec!["kw:enum A = B | C", "$x = 1"]);
+	}
+
+	#[test]
+	fn names_with_dashes() {
+		assert_eq!(lines("p\n\t-webkit-box-flex: 1\n\t/* c */ --brand: var(--x) -a"), vec![
+			"p",
+			"> -webkit-box-flex : 1",
+			"--brand : var ( --x ) - a",
+			"<",
+		]);
+	}
+}